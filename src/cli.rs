@@ -1,9 +1,14 @@
 //! Command Line Interface types and Argument parsing
 
+use crate::interval::IntervalDuration;
+use crate::tasks::local_query::FORM_ENCODED_MIME_TYPE;
+use crate::tasks::provider_manager::{
+    is_valid_provider_name, is_valid_provider_version, Registry, REPOSITORY_URL,
+};
 use anyhow::{anyhow, Error};
 use clap::{Parser, Subcommand, ValueEnum};
-use fiberplane::models::proxies::ProxyToken;
-use std::{net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use serde_json::{Map, Value};
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
 use tracing::Level;
 use url::Url;
 
@@ -18,7 +23,7 @@ use url::Url;
 /// - online in Studio web user interface.
 pub struct Arguments {
     /// Path to directory containing provider WASM files
-    #[clap(long, env)]
+    #[clap(long, env, global = true)]
     pub wasm_dir: Option<PathBuf>,
 
     /// Web-socket endpoint of the Fiberplane API (leave path empty to use the default path)
@@ -26,11 +31,12 @@ pub struct Arguments {
     pub api_base: Url,
 
     /// Token used to authenticate against the Fiberplane API. This is created through the CLI by running the command: `fp daemon add`
-    #[clap(long, short, env)]
-    pub token: Option<ProxyToken>,
+    #[clap(long, short, env, global = true)]
+    pub token: Option<String>,
 
-    /// Path to data sources YAML file
-    #[clap(long, short, env)]
+    /// Path to data sources YAML file, or to a directory of YAML files that
+    /// are merged together
+    #[clap(long, short, env, global = true)]
     pub data_sources_path: Option<PathBuf>,
 
     /// Max retries to connect to the fiberplane server before giving up on failed connections
@@ -41,10 +47,53 @@ pub struct Arguments {
     #[clap(long, short, env)]
     pub listen_address: Option<SocketAddr>,
 
+    /// Serve a local HTTP API to call the providers of the data sources (requires --listen-address)
+    #[clap(long, env)]
+    pub local_api: bool,
+
+    /// Token that requests to the local API must give in an `Authorization: Bearer` header (required unless --listen-address is a loopback address)
+    #[clap(long, env)]
+    pub local_api_token: Option<String>,
+
     /// Interval to check the status of each data source ("30s" = 30 seconds, "5m" = 5 minutes, "1h" = 1 hour)
     #[clap(long, short, env, default_value = "5m")]
     pub status_check_interval: IntervalDuration,
 
+    /// Maximum duration of a query to a data source before it fails with a timeout error ("0s" disables
+    /// the timeout). Data sources can override it with `queryTimeout`
+    #[clap(long, env, default_value = "2m")]
+    pub query_timeout: IntervalDuration,
+
+    /// Maximum number of queries running at the same time, for all data sources together (unlimited by
+    /// default)
+    #[clap(long, env, value_parser = parse_max_concurrent_queries)]
+    pub max_concurrent_queries: Option<usize>,
+
+    /// Maximum number of queries running at the same time against a single data source (unlimited by
+    /// default). Data sources can override it with `maxConcurrentQueries`
+    #[clap(long, env, value_parser = parse_max_concurrent_queries)]
+    pub max_concurrent_queries_per_data_source: Option<usize>,
+
+    /// Maximum number of queries waiting for a free slot (globally, and for each data source) when the
+    /// concurrency limits are reached. Further queries fail with an "overloaded" error
+    #[clap(long, env, default_value = "100")]
+    pub max_queued_queries: usize,
+
+    /// Maximum number of synchronous provider calls (formatting query results into cells, extracting
+    /// data) running at the same time on dedicated threads
+    #[clap(long, env, default_value = "4", value_parser = parse_max_blocking_calls)]
+    pub max_blocking_calls: usize,
+
+    /// Interval to check the data sources file for changes, and reload it if it changed ("0s" disables
+    /// checking for changes, sending SIGHUP to the daemon always reloads the file)
+    #[clap(long, env, default_value = "10s")]
+    pub data_sources_reload_interval: IntervalDuration,
+
+    /// Interval to check the provider modules of the data sources for changes, and reload the providers whose
+    /// module changed ("0s" disables reloading the providers)
+    #[clap(long, env, default_value = "10s")]
+    pub providers_reload_interval: IntervalDuration,
+
     /// Set the logging level for the daemon (trace, debug, info, warn, error)
     #[clap(long, env)]
     pub log_level: Option<Level>,
@@ -69,15 +118,147 @@ pub enum Action {
     },
     /// Pull Fiberplane providers
     Pull {
-        /// Names of the providers to fetch
-        #[arg(value_enum)]
-        names: Vec<BuiltinProvider>,
-        /// Pull all known providers
+        /// Names of the providers to fetch (like prometheus, loki, sentry, https or elasticsearch, or any
+        /// provider published in the registry), optionally followed by the release to use, like
+        /// `prometheus@v2.3.0`
+        names: Vec<ProviderSpec>,
+        /// Where to pull the providers from: the URL of the repository or of a mirror, or a local directory
+        /// (a path or a `file://` URL) with the same layout
+        #[clap(long, env = "PROVIDERS_REGISTRY", default_value = REPOSITORY_URL)]
+        registry: Registry,
+        /// Pull all the built-in providers
         #[clap(long, short)]
         all: bool,
+        /// Replace the installed providers that were pulled from a different release, or that follow the
+        /// latest version
+        #[clap(long, conflicts_with = "force")]
+        update: bool,
+        /// Replace the installed providers, even if they were pulled from the same release
+        #[clap(long)]
+        force: bool,
+        /// Base64-encoded ed25519 public key used to check the signature of the checksums manifest of the
+        /// pulled providers. Without it, the providers are only checked against the manifest
+        #[clap(long, env = "PROVIDERS_PUBLIC_KEY")]
+        public_key: Option<String>,
+        /// Install the providers of releases that don't publish a checksums manifest, without verifying
+        /// them
+        #[clap(long, conflicts_with = "public_key")]
+        allow_unverified: bool,
+    },
+    /// Inspect the installed providers
+    #[clap(alias = "provider")]
+    Providers {
+        #[clap(subcommand)]
+        action: ProvidersAction,
+    },
+    /// Check the status of every data source once, without connecting to
+    /// Fiberplane, and print a report.
+    ///
+    /// Exits with a non-zero status code if any data source fails its check.
+    Status {
+        /// Format of the report
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Check the configuration paths, the data sources files, the providers,
+    /// the token, the connection to Fiberplane and the status of every data
+    /// source in turn, and print a checklist with hints to fix the failures.
+    ///
+    /// Exits with a non-zero status code if any check fails.
+    Doctor {
+        /// Format of the checklist
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+        /// Web-socket endpoint of the Fiberplane API to check the connection to (defaults to the
+        /// `--api-base` of the daemon)
+        #[arg(long = "api-base")]
+        api_base: Option<Url>,
+    },
+    /// Run a query against a data source with its provider, without going
+    /// through Studio, and print the result.
+    ///
+    /// The query runs with the config and the query timeout of the data source.
+    Query {
+        /// Name of the data source to query
+        data_source: String,
+        /// Type of the query, like `x-timeseries` (see the query types supported by the providers with
+        /// `fpd providers list`)
+        query_type: String,
+        /// Data of the query, form-encoded by default, like `q=up`
+        #[clap(default_value = "")]
+        query_data: String,
+        /// MIME type of the query data
+        #[clap(long, default_value = FORM_ENCODED_MIME_TYPE)]
+        mime_type: String,
+        /// Turn the result into notebook cells with the provider, like Studio does
+        #[clap(long)]
+        cells: bool,
+        /// Format of the result
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
     },
 }
 
+#[derive(Subcommand)]
+pub enum ProvidersAction {
+    /// List the providers of the wasm directory, with their metadata, whether
+    /// they load, the query types they support and the data sources using them
+    List {
+        /// Format of the list
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Print the config schema of a provider, which describes the config of its
+    /// data sources, and the query types it supports with their schema
+    Inspect {
+        /// Name of a provider of the wasm directory (optionally followed by `@<version>`), or path to a
+        /// provider module
+        provider: String,
+        /// Data source config to ask for the supported query types with, as a JSON object (some providers
+        /// support different query types depending on their config)
+        #[clap(long, default_value = "{}")]
+        config: JsonObject,
+        /// Format of the description
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// Pack the providers of the wasm directory into a bundle, with a
+    /// manifest of their names, versions and checksums, to install them
+    /// without network access with `install-bundle`
+    Bundle {
+        /// Path of the bundle to create
+        output: PathBuf,
+        /// Base64-encoded ed25519 private key (a 32 bytes seed) to sign the manifest of the bundle with, so
+        /// that `install-bundle` can check it with `--public-key`
+        #[clap(long, env = "PROVIDERS_SIGNING_KEY")]
+        signing_key: Option<String>,
+    },
+    /// Check a bundle created with `bundle` and install its providers into the
+    /// wasm directory
+    InstallBundle {
+        /// Path of the bundle to install
+        bundle: PathBuf,
+        /// Replace the installed providers that come from a different release
+        #[clap(long, conflicts_with = "force")]
+        update: bool,
+        /// Replace the installed providers, even if they come from the same release
+        #[clap(long)]
+        force: bool,
+        /// Base64-encoded ed25519 public key used to check the signature of the manifest of the bundle.
+        /// Without it, the providers are only checked against the manifest
+        #[clap(long, env = "PROVIDERS_PUBLIC_KEY")]
+        public_key: Option<String>,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable table
+    Table,
+    /// JSON document
+    Json,
+}
+
 #[derive(Subcommand)]
 pub enum ConfigAction {
     /// Print the canonical configuration paths, used when no environment variable
@@ -89,16 +270,24 @@ pub enum ConfigAction {
         #[arg(value_enum)]
         query: Option<ConfigPathQuery>,
     },
+    /// Check the data sources configuration against the config schema of
+    /// their providers, and print the problems found.
+    ///
+    /// Exits with a non-zero status code if any data source is invalid.
+    Validate,
 }
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum ConfigPathQuery {
     /// Path to the data_sources.yaml file to use to configure the daemon
     DataSources,
+    /// Path to the directory that can contain data sources files instead of
+    /// data_sources.yaml
+    DataSourcesDir,
     /// Path to the directory containing the WebAssembly providers
     WasmDir,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum BuiltinProvider {
     /// Prometheus provider
     Prometheus,
@@ -124,39 +313,160 @@ impl BuiltinProvider {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct IntervalDuration(pub Duration);
+/// A provider to pull, optionally pinned to a release (`name@version`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderSpec {
+    /// Name of the provider, which doesn't have to be a built-in provider
+    pub name: String,
+    /// Release tag to pull the provider from, the latest version if absent
+    pub version: Option<String>,
+}
 
-impl FromStr for IntervalDuration {
+impl FromStr for ProviderSpec {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split_at(s.len() - 1) {
-            (s, "s") => Ok(IntervalDuration(Duration::from_secs(u64::from_str(s)?))),
-            (s, "m") => Ok(IntervalDuration(Duration::from_secs(
-                u64::from_str(s)? * 60,
-            ))),
-            (s, "h") => Ok(IntervalDuration(Duration::from_secs(
-                u64::from_str(s)? * 60 * 60,
-            ))),
-            _ => Err(anyhow!("invalid interval")),
+        let (name, version) = match s.split_once('@') {
+            Some((_, "")) => return Err(anyhow!("missing version after '@' in '{s}'")),
+            Some((name, version)) => (name, Some(version.to_string())),
+            None => (s, None),
+        };
+        if !is_valid_provider_name(name) {
+            return Err(anyhow!(
+                "invalid provider name '{name}', expected lowercase letters, digits, '-' or '_'"
+            ));
+        }
+        if let Some(version) = version
+            .as_deref()
+            .filter(|version| !is_valid_provider_version(version))
+        {
+            return Err(anyhow!(
+                "invalid provider version '{version}', expected a release like \"v2.3.0\""
+            ));
         }
+        Ok(ProviderSpec {
+            name: name.to_string(),
+            version,
+        })
+    }
+}
+
+fn parse_max_concurrent_queries(s: &str) -> Result<usize, Error> {
+    match usize::from_str(s)? {
+        0 => Err(anyhow!(
+            "the maximum number of concurrent queries must be greater than 0"
+        )),
+        max_concurrent => Ok(max_concurrent),
+    }
+}
+
+fn parse_max_blocking_calls(s: &str) -> Result<usize, Error> {
+    match usize::from_str(s)? {
+        0 => Err(anyhow!(
+            "the maximum number of blocking calls must be greater than 0"
+        )),
+        max_blocking_calls => Ok(max_blocking_calls),
+    }
+}
+
+/// A JSON object given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonObject(pub Map<String, Value>);
+
+impl FromStr for JsonObject {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+            .map(JsonObject)
+            .map_err(|err| anyhow!("expected a JSON object: {err}"))
     }
 }
 
 #[test]
-fn interval_parsing() {
+fn provider_spec_parsing() {
+    assert_eq!(
+        ProviderSpec {
+            name: "prometheus".to_string(),
+            version: None
+        },
+        "prometheus".parse().unwrap()
+    );
     assert_eq!(
-        IntervalDuration(Duration::from_secs(30)),
-        "30s".parse().unwrap()
+        ProviderSpec {
+            name: "elasticsearch".to_string(),
+            version: Some("v2.3.0".to_string())
+        },
+        "elasticsearch@v2.3.0".parse().unwrap()
     );
     assert_eq!(
-        IntervalDuration(Duration::from_secs(60)),
-        "1m".parse().unwrap()
+        ProviderSpec {
+            name: "acme_logs-2".to_string(),
+            version: None
+        },
+        "acme_logs-2".parse().unwrap()
     );
+    ProviderSpec::from_str("prometheus@").expect_err("missing version");
+    ProviderSpec::from_str("").expect_err("empty name");
+    ProviderSpec::from_str("../prometheus").expect_err("path as name");
+    ProviderSpec::from_str("Graphite").expect_err("uppercase name");
+    ProviderSpec::from_str("prometheus@../../x").expect_err("path as version");
+    ProviderSpec::from_str("prometheus@..").expect_err("parent directory as version");
+}
+
+#[test]
+fn max_concurrent_queries_parsing() {
+    let args = Arguments::try_parse_from(["fpd", "--max-concurrent-queries", "4"]).unwrap();
+    assert_eq!(args.max_concurrent_queries, Some(4));
+    assert!(Arguments::try_parse_from(["fpd", "--max-concurrent-queries", "0"]).is_err());
+    assert!(
+        Arguments::try_parse_from(["fpd", "--max-concurrent-queries-per-data-source", "0"])
+            .is_err()
+    );
+}
+
+#[test]
+fn max_blocking_calls_parsing() {
+    let args = Arguments::try_parse_from(["fpd"]).unwrap();
+    assert_eq!(args.max_blocking_calls, 4);
+    let args = Arguments::try_parse_from(["fpd", "--max-blocking-calls", "1"]).unwrap();
+    assert_eq!(args.max_blocking_calls, 1);
+    assert!(Arguments::try_parse_from(["fpd", "--max-blocking-calls", "0"]).is_err());
+}
+
+#[test]
+fn doctor_arguments_parsing() {
+    <Arguments as clap::CommandFactory>::command().debug_assert();
+
+    let args = Arguments::try_parse_from([
+        "fpd",
+        "doctor",
+        "--token",
+        "secret",
+        "--api-base",
+        "ws://127.0.0.1:3000",
+    ])
+    .unwrap();
+    assert_eq!(args.token.as_deref(), Some("secret"));
+    match args.subcommand {
+        Some(Action::Doctor { api_base, .. }) => {
+            assert_eq!(api_base.unwrap().as_str(), "ws://127.0.0.1:3000/")
+        }
+        _ => panic!("expected the doctor subcommand"),
+    }
+
+    let args = Arguments::try_parse_from(["fpd", "--token", "secret", "doctor"]).unwrap();
+    assert_eq!(args.token.as_deref(), Some("secret"));
+}
+
+#[test]
+fn json_object_parsing() {
+    let mut config = Map::new();
+    config.insert("url".to_string(), Value::from("http://localhost:9090"));
     assert_eq!(
-        IntervalDuration(Duration::from_secs(3600)),
-        "1h".parse().unwrap()
+        JsonObject(config),
+        r#"{"url": "http://localhost:9090"}"#.parse().unwrap()
     );
-    IntervalDuration::from_str("3d").expect_err("invalid interval");
+    JsonObject::from_str("[]").expect_err("not an object");
+    JsonObject::from_str("url=http://localhost").expect_err("not JSON");
 }
@@ -0,0 +1,306 @@
+//! Loading of the data sources configuration file
+
+use crate::tasks::service::ProxyDataSource;
+use fiberplane::models::names::Name;
+use serde_path_to_error::Segment;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt, io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::fs;
+
+mod interpolation;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Data sources file not found at {} ({error})", path.display())]
+    NotFound { path: PathBuf, error: io::Error },
+    #[error("Insufficient permissions to read data sources file {} ({error})", path.display())]
+    PermissionDenied { path: PathBuf, error: io::Error },
+    #[error("Unable to read data sources file at {}: {error}", path.display())]
+    Read { path: PathBuf, error: io::Error },
+    /// The file is not valid YAML, or is not a list of data sources
+    #[error("{}: {message}", Position { path, location })]
+    Malformed {
+        path: PathBuf,
+        location: Option<Location>,
+        message: String,
+    },
+    /// One of the entries of the list is not a valid data source
+    #[error("{}: invalid data source {}: {message}", Position { path, location }, describe_entry(*index, name, field))]
+    InvalidDataSource {
+        path: PathBuf,
+        location: Option<Location>,
+        /// Index of the entry in the list
+        index: usize,
+        /// Name of the data source, if the entry has one (even an invalid one)
+        name: Option<String>,
+        /// Path to the field that failed, if the error isn't about the whole entry
+        field: Option<String>,
+        message: String,
+    },
+    #[error("{}: invalid config for data source '{name}': {error}", path.display())]
+    InvalidConfig {
+        path: PathBuf,
+        name: Name,
+        error: interpolation::Error,
+    },
+    #[error("Data source '{name}' is defined twice: as {first} and as {second}")]
+    DuplicateName {
+        name: Name,
+        first: Definition,
+        second: Definition,
+    },
+}
+
+/// Where a data source is defined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub path: PathBuf,
+    /// Index of the entry in the list
+    pub index: usize,
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry #{} of {}", self.index + 1, self.path.display())
+    }
+}
+
+/// Position of an error in a data sources file (both 1-based)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+struct Position<'a> {
+    path: &'a Path,
+    location: &'a Option<Location>,
+}
+
+impl fmt::Display for Position<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(
+                f,
+                "{}:{}:{}",
+                self.path.display(),
+                location.line,
+                location.column
+            ),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+fn describe_entry(index: usize, name: &Option<String>, field: &Option<String>) -> String {
+    let mut description = format!("#{}", index + 1);
+    if let Some(name) = name {
+        description.push_str(&format!(" ('{name}')"));
+    }
+    if let Some(field) = field {
+        description.push_str(&format!(", field '{field}'"));
+    }
+    description
+}
+
+/// Content of a data sources file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataSourcesFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Read the data sources file at the given path or, if the path is a
+/// directory, every `*.yaml` file it contains sorted by file name.
+///
+/// Hidden files are ignored, which skips the `..data` entries of mounted
+/// Kubernetes ConfigMaps.
+pub async fn read(path: &Path) -> Result<Vec<DataSourcesFile>, Error> {
+    let metadata = fs::metadata(path)
+        .await
+        .map_err(|error| read_error(path, error))?;
+    if !metadata.is_dir() {
+        return Ok(vec![read_file(path).await?]);
+    }
+
+    let mut paths = Vec::new();
+    let mut entries = fs::read_dir(path)
+        .await
+        .map_err(|error| read_error(path, error))?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|error| read_error(path, error))?
+    {
+        let entry_path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry_path.extension() == Some(OsStr::new("yaml")) {
+            paths.push(entry_path);
+        }
+    }
+    paths.sort();
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        files.push(read_file(&path).await?);
+    }
+    Ok(files)
+}
+
+async fn read_file(path: &Path) -> Result<DataSourcesFile, Error> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => Ok(DataSourcesFile {
+            path: path.to_path_buf(),
+            contents,
+        }),
+        Err(error) => Err(read_error(path, error)),
+    }
+}
+
+fn read_error(path: &Path, error: io::Error) -> Error {
+    let path = path.to_path_buf();
+    match error.kind() {
+        io::ErrorKind::NotFound => Error::NotFound { path, error },
+        io::ErrorKind::PermissionDenied => Error::PermissionDenied { path, error },
+        _ => Error::Read { path, error },
+    }
+}
+
+/// Parse and merge the data sources of all the files, resolving the
+/// environment variables and secret files referenced in their configs
+pub fn parse(files: &[DataSourcesFile]) -> Result<Vec<ProxyDataSource>, Error> {
+    let mut data_sources = Vec::new();
+    let mut definitions: HashMap<Name, Definition> = HashMap::new();
+    for file in files {
+        for (index, data_source) in parse_file(&file.path, &file.contents)?
+            .into_iter()
+            .enumerate()
+        {
+            let definition = Definition {
+                path: file.path.clone(),
+                index,
+            };
+            if let Some(first) = definitions.insert(data_source.name.clone(), definition.clone()) {
+                return Err(Error::DuplicateName {
+                    name: data_source.name,
+                    first,
+                    second: definition,
+                });
+            }
+            data_sources.push(data_source);
+        }
+    }
+    Ok(data_sources)
+}
+
+fn parse_file(path: &Path, contents: &str) -> Result<Vec<ProxyDataSource>, Error> {
+    let deserializer = serde_yaml::Deserializer::from_str(contents);
+    let mut data_sources: Vec<ProxyDataSource> = serde_path_to_error::deserialize(deserializer)
+        .map_err(|error| invalid_file_error(path, contents, error))?;
+
+    for (index, data_source) in data_sources.iter_mut().enumerate() {
+        if data_source.provider_path.is_some() && data_source.provider_version.is_some() {
+            return Err(Error::InvalidDataSource {
+                path: path.to_path_buf(),
+                location: None,
+                index,
+                name: Some(data_source.name.to_string()),
+                field: Some("providerVersion".to_string()),
+                message: "can't be used together with `providerPath`".to_string(),
+            });
+        }
+        data_source.config =
+            interpolation::resolve_config(&data_source.config).map_err(|error| {
+                Error::InvalidConfig {
+                    path: path.to_path_buf(),
+                    name: data_source.name.clone(),
+                    error,
+                }
+            })?;
+    }
+    Ok(data_sources)
+}
+
+fn invalid_file_error(
+    path: &Path,
+    contents: &str,
+    error: serde_path_to_error::Error<serde_yaml::Error>,
+) -> Error {
+    let location = error.inner().location().map(|location| Location {
+        line: location.line(),
+        column: location.column(),
+    });
+    let mut message = yaml_error_message(&error);
+
+    let mut segments = error.path().iter();
+    let index = match segments.next() {
+        Some(Segment::Seq { index }) => *index,
+        _ => {
+            return Error::Malformed {
+                path: path.to_path_buf(),
+                location,
+                message,
+            }
+        }
+    };
+
+    let mut field = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Seq { index } => field.push_str(&format!("[{index}]")),
+            segment if field.is_empty() => field.push_str(&segment.to_string()),
+            segment => field.push_str(&format!(".{segment}")),
+        }
+    }
+
+    // The file is valid YAML at this point, so the entry can be inspected to
+    // give more context about the error
+    let name = serde_yaml::from_str::<serde_yaml::Value>(contents)
+        .ok()
+        .and_then(|value| {
+            value
+                .get(index)
+                .and_then(|entry| entry.get("name"))
+                .and_then(|name| name.as_str())
+                .map(str::to_string)
+        });
+    if let (Some(name), "name") = (&name, field.as_str()) {
+        if let Err(err) = Name::new(name.as_str()) {
+            message = format!("'{name}' is not a valid name: {err}");
+        }
+    }
+
+    Error::InvalidDataSource {
+        path: path.to_path_buf(),
+        location,
+        index,
+        name,
+        field: if field.is_empty() { None } else { Some(field) },
+        message,
+    }
+}
+
+/// Return the message of the error, without the path and position that
+/// serde_yaml adds to it since they're reported separately
+fn yaml_error_message(error: &serde_path_to_error::Error<serde_yaml::Error>) -> String {
+    let mut message = error.inner().to_string();
+    if let Some(location) = error.inner().location() {
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        if message.ends_with(&suffix) {
+            message.truncate(message.len() - suffix.len());
+        }
+    }
+    // The file is a list, so the path serde_yaml adds (which is sometimes the
+    // path of the parent of the failing field) always starts with ".["
+    match message.split_once(": ") {
+        Some((path, stripped)) if path.starts_with(".[") => stripped.to_string(),
+        _ => message,
+    }
+}
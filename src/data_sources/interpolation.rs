@@ -0,0 +1,145 @@
+//! Resolution of environment variables and secret files in data source configs
+//!
+//! String values in a config can refer to environment variables with `${NAME}`,
+//! or `${NAME:-default}` to fall back to a default value when the variable is
+//! unset or empty. `$$` produces a literal `$`.
+//!
+//! A value that is a map with a single `fromFile` key is replaced by the content
+//! of that file (without its trailing newline), which allows mounting secrets
+//! instead of writing them in the configuration:
+//!
+//! ```yaml
+//! config:
+//!   url: https://sentry.io
+//!   token:
+//!     fromFile: /run/secrets/sentry-token
+//! ```
+
+use serde_json::{Map, Value};
+use std::{env, fs, io, path::PathBuf};
+use thiserror::Error;
+
+const FROM_FILE_KEY: &str = "fromFile";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{key}: environment variable '{name}' is not set")]
+    MissingEnvVar { key: String, name: String },
+    #[error("{key}: invalid environment variable name '{name}'")]
+    InvalidEnvVarName { key: String, name: String },
+    #[error("{key}: missing closing '}}' after '${{'")]
+    Unterminated { key: String },
+    #[error("{key}: 'fromFile' must be a file path")]
+    InvalidFromFile { key: String },
+    #[error("{key}: unable to read secret file '{}': {source}", path.display())]
+    SecretFile {
+        key: String,
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+/// Return a copy of the config with all environment variables and secret
+/// files resolved
+pub fn resolve_config(config: &Map<String, Value>) -> Result<Map<String, Value>, Error> {
+    config
+        .iter()
+        .map(|(key, value)| Ok((key.clone(), resolve_value(key, value)?)))
+        .collect()
+}
+
+fn resolve_value(key: &str, value: &Value) -> Result<Value, Error> {
+    match value {
+        Value::String(string) => Ok(Value::String(interpolate(key, string)?)),
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| resolve_value(&format!("{key}[{index}]"), value))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        Value::Object(map) if map.len() == 1 && map.contains_key(FROM_FILE_KEY) => {
+            let path = match &map[FROM_FILE_KEY] {
+                Value::String(path) => PathBuf::from(interpolate(key, path)?),
+                _ => {
+                    return Err(Error::InvalidFromFile {
+                        key: key.to_string(),
+                    })
+                }
+            };
+            match fs::read_to_string(&path) {
+                Ok(content) => Ok(Value::String(
+                    content.trim_end_matches(&['\n', '\r'][..]).to_string(),
+                )),
+                Err(source) => Err(Error::SecretFile {
+                    key: key.to_string(),
+                    path,
+                    source,
+                }),
+            }
+        }
+        Value::Object(map) => map
+            .iter()
+            .map(|(child_key, value)| {
+                Ok((
+                    child_key.clone(),
+                    resolve_value(&format!("{key}.{child_key}"), value)?,
+                ))
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Object),
+        value => Ok(value.clone()),
+    }
+}
+
+/// Replace the `${NAME}` and `${NAME:-default}` references in the given string
+fn interpolate(key: &str, input: &str) -> Result<String, Error> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(index) = rest.find('$') {
+        output.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            output.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| Error::Unterminated {
+                key: key.to_string(),
+            })?;
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            if !is_valid_env_var_name(name) {
+                return Err(Error::InvalidEnvVarName {
+                    key: key.to_string(),
+                    name: name.to_string(),
+                });
+            }
+            match (env::var(name), default) {
+                (Ok(value), Some(default)) if value.is_empty() => output.push_str(default),
+                (Ok(value), _) => output.push_str(&value),
+                (Err(_), Some(default)) => output.push_str(default),
+                (Err(_), None) => {
+                    return Err(Error::MissingEnvVar {
+                        key: key.to_string(),
+                        name: name.to_string(),
+                    })
+                }
+            }
+            rest = &after[end + 1..];
+        } else {
+            // A lone '$' is kept as is
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn is_valid_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
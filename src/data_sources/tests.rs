@@ -0,0 +1,472 @@
+use super::{parse, read, DataSourcesFile, Error, Location};
+use crate::tasks::service::ProxyDataSource;
+use fiberplane::models::names::Name;
+use serde_json::json;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tempfile::TempDir;
+
+fn test_file(path: &str, contents: &str) -> DataSourcesFile {
+    DataSourcesFile {
+        path: PathBuf::from(path),
+        contents: contents.to_string(),
+    }
+}
+
+fn parse_test_file(contents: &str) -> Result<Vec<ProxyDataSource>, Error> {
+    parse(&[test_file("data_sources.yaml", contents)])
+}
+
+#[test]
+fn resolves_environment_variables() {
+    env::set_var("FPD_TEST_PROMETHEUS_HOST", "prometheus.internal");
+    env::set_var("FPD_TEST_EMPTY", "");
+    let yaml = "
+- name: prometheus-production
+  providerType: prometheus
+  config:
+    url: https://${FPD_TEST_PROMETHEUS_HOST}:9090
+    headers:
+      - Authorization: Bearer ${FPD_TEST_UNSET:-anonymous}
+    path: ${FPD_TEST_EMPTY:-/api}
+    literal: $$HOME costs $5";
+    let data_sources = parse_test_file(yaml).unwrap();
+    assert_eq!(
+        data_sources[0].config,
+        *json!({
+            "url": "https://prometheus.internal:9090",
+            "headers": [{ "Authorization": "Bearer anonymous" }],
+            "path": "/api",
+            "literal": "$HOME costs $5"
+        })
+        .as_object()
+        .unwrap()
+    );
+}
+
+#[test]
+fn rejects_missing_environment_variables() {
+    let yaml = "
+- name: sentry
+  providerType: sentry
+  config:
+    token: ${FPD_TEST_MISSING_TOKEN}";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        "data_sources.yaml: invalid config for data source 'sentry': token: environment variable 'FPD_TEST_MISSING_TOKEN' is not set"
+    );
+
+    let yaml = "
+- name: sentry
+  providerType: sentry
+  config:
+    token: ${FPD_TEST_MISSING_TOKEN";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert_eq!(
+        format!("{err}"),
+        "data_sources.yaml: invalid config for data source 'sentry': token: missing closing '}' after '${'"
+    );
+}
+
+#[test]
+fn reads_secret_files() {
+    let dir = TempDir::new().unwrap();
+    let secret_path = dir.path().join("secret");
+    fs::write(&secret_path, "s3cr3t\n").unwrap();
+    env::set_var("FPD_TEST_SECRET_PATH", &secret_path);
+
+    let yaml = "
+- name: elasticsearch
+  providerType: elasticsearch
+  config:
+    url: http://localhost:9200
+    password:
+      fromFile: ${FPD_TEST_SECRET_PATH}
+    nested:
+      token:
+        fromFile: ${FPD_TEST_SECRET_PATH}";
+    let data_sources = parse_test_file(yaml).unwrap();
+
+    assert_eq!(
+        data_sources[0].config,
+        *json!({
+            "url": "http://localhost:9200",
+            "password": "s3cr3t",
+            "nested": { "token": "s3cr3t" }
+        })
+        .as_object()
+        .unwrap()
+    );
+
+    let yaml = "
+- name: elasticsearch
+  providerType: elasticsearch
+  config:
+    password:
+      fromFile: /nonexistent/fpd-secret";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert!(format!("{err}")
+        .starts_with("data_sources.yaml: invalid config for data source 'elasticsearch': password: unable to read secret file '/nonexistent/fpd-secret'"));
+}
+
+#[test]
+fn reports_the_position_of_syntax_errors() {
+    let yaml = "
+- name: prometheus
+  providerType: prometheus
+  config: {url: http://localhost:9090
+";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Malformed {
+            location: Some(Location { line: 5, .. }),
+            ..
+        }
+    ));
+
+    let err = parse_test_file("name: prometheus").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "data_sources.yaml:1:5: invalid type: map, expected a sequence"
+    );
+}
+
+#[test]
+fn reports_the_entry_and_field_of_invalid_data_sources() {
+    let yaml = "
+- name: prometheus
+  providerType: prometheus
+  config: {}
+- name: loki
+  config: {}
+- name: sentry
+  providerType: sentry
+  config: https://sentry.io";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "data_sources.yaml:5:7: invalid data source #2 ('loki'): missing field `providerType`"
+    );
+
+    let yaml = "
+- name: sentry
+  providerType: sentry
+  config: https://sentry.io";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "data_sources.yaml:4:11: invalid data source #1 ('sentry'), field 'config': invalid type: string \"https://sentry.io\", expected a map"
+    );
+}
+
+#[test]
+fn explains_invalid_names() {
+    for name in ["Prometheus", "prometheus_prod", "-prometheus", ""] {
+        let yaml = format!(
+            "
+- name: '{name}'
+  providerType: prometheus
+  config: {{}}"
+        );
+        match parse_test_file(&yaml).unwrap_err() {
+            Error::InvalidDataSource {
+                index: 0,
+                field: Some(field),
+                message,
+                ..
+            } => {
+                assert_eq!(field, "name");
+                assert_eq!(
+                    message,
+                    format!(
+                        "'{name}' is not a valid name: {}",
+                        Name::new(name).unwrap_err()
+                    )
+                );
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
+
+#[test]
+fn rejects_duplicate_names() {
+    let yaml = "
+- name: prometheus
+  providerType: prometheus
+  config: {}
+- name: loki
+  providerType: loki
+  config: {}
+- name: prometheus
+  providerType: prometheus
+  config: {}";
+    let err = parse_test_file(yaml).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Data source 'prometheus' is defined twice: as entry #1 of data_sources.yaml and as entry #3 of data_sources.yaml"
+    );
+}
+
+#[test]
+fn rejects_duplicate_names_across_files() {
+    let files = [
+        test_file(
+            "data_sources.d/metrics.yaml",
+            "
+- name: prometheus
+  providerType: prometheus
+  config: {}",
+        ),
+        test_file(
+            "data_sources.d/logs.yaml",
+            "
+- name: loki
+  providerType: loki
+  config: {}
+- name: prometheus
+  providerType: prometheus
+  config: {}",
+        ),
+    ];
+    let err = parse(&files).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Data source 'prometheus' is defined twice: as entry #1 of data_sources.d/metrics.yaml and as entry #2 of data_sources.d/logs.yaml"
+    );
+}
+
+#[tokio::test]
+async fn merges_the_files_of_a_directory() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(
+        dir.join("b-logs.yaml"),
+        "
+- name: loki
+  providerType: loki
+  config: {}",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("a-metrics.yaml"),
+        "
+- name: prometheus
+  providerType: prometheus
+  config: {}",
+    )
+    .unwrap();
+    fs::write(dir.join("README.md"), "Not a data sources file").unwrap();
+    fs::write(dir.join(".hidden.yaml"), "not: [valid").unwrap();
+    fs::write(dir.join("invalid.yaml"), "- name: Invalid").unwrap();
+
+    let files = read(dir).await.unwrap();
+    let paths: Vec<_> = files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            dir.join("a-metrics.yaml"),
+            dir.join("b-logs.yaml"),
+            dir.join("invalid.yaml")
+        ]
+    );
+
+    let err = parse(&files).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with(&format!("{}:", dir.join("invalid.yaml").display())));
+
+    fs::remove_file(dir.join("invalid.yaml")).unwrap();
+    let data_sources = parse(&read(dir).await.unwrap()).unwrap();
+    let names: Vec<_> = data_sources
+        .iter()
+        .map(|data_source| data_source.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["prometheus", "loki"]);
+}
+
+#[test]
+fn parses_status_check_overrides() {
+    let yaml = "
+- name: elasticsearch
+  providerType: elasticsearch
+  config: {}
+  statusCheck:
+    interval: 5m
+    initialRetryDelay: 30s
+    backoffFactor: 2
+- name: prometheus
+  providerType: prometheus
+  config: {}
+  statusCheck:
+    enabled: false";
+    let data_sources = parse_test_file(yaml).unwrap();
+    let status_check = data_sources[0].status_check.clone().unwrap();
+    assert_eq!(status_check.interval.unwrap().0, Duration::from_secs(300));
+    assert_eq!(
+        status_check.initial_retry_delay.unwrap().0,
+        Duration::from_secs(30)
+    );
+    assert_eq!(status_check.backoff_factor, Some(2.0));
+    assert_eq!(
+        data_sources[1].status_check.as_ref().unwrap().enabled,
+        Some(false)
+    );
+
+    let yaml = "
+- name: elasticsearch
+  providerType: elasticsearch
+  config: {}
+  statusCheck:
+    backoffFactor: 0.5";
+    match parse_test_file(yaml).unwrap_err() {
+        Error::InvalidDataSource { field, message, .. } => {
+            assert_eq!(field.as_deref(), Some("statusCheck.backoffFactor"));
+            assert_eq!(
+                message,
+                "the backoff factor must be greater than 1, found 0.5"
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+
+    let yaml = "
+- name: elasticsearch
+  providerType: elasticsearch
+  config: {}
+  statusCheck:
+    interval: 18446744073709551615s";
+    match parse_test_file(yaml).unwrap_err() {
+        Error::InvalidDataSource { field, message, .. } => {
+            assert_eq!(field.as_deref(), Some("statusCheck.interval"));
+            assert_eq!(
+                message,
+                "invalid interval '18446744073709551615s', the interval can't be longer than 8760h (365 days)"
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn parse_provider_modules() {
+    let data_sources = parse_test_file(
+        "- name: prometheus-canary
+  providerType: prometheus
+  providerVersion: v2.4.0
+  config: {}
+- name: prometheus-local
+  providerType: prometheus
+  providerPath: /build/prometheus.wasm
+  config: {}
+- name: prometheus
+  providerType: prometheus
+  config: {}
+",
+    )
+    .unwrap();
+    let wasm_dir = Path::new("/providers");
+    assert_eq!(
+        data_sources
+            .iter()
+            .map(|data_source| data_source.provider_module(wasm_dir))
+            .collect::<Vec<_>>(),
+        vec![
+            PathBuf::from("/providers/prometheus@v2.4.0.wasm"),
+            PathBuf::from("/build/prometheus.wasm"),
+            PathBuf::from("/providers/prometheus.wasm"),
+        ]
+    );
+
+    let err = parse_test_file(
+        "- name: prometheus
+  providerType: prometheus
+  providerVersion: ../../etc/v2
+  config: {}
+",
+    )
+    .unwrap_err();
+    assert!(
+        matches!(err, Error::InvalidDataSource { field: Some(field), .. } if field == "providerVersion")
+    );
+
+    let err = parse_test_file(
+        "- name: prometheus
+  providerType: prometheus
+  providerPath: ./prometheus.wasm
+  providerVersion: v2.4.0
+  config: {}
+",
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "data_sources.yaml: invalid data source #1 ('prometheus'), field 'providerVersion': can't be used together with `providerPath`"
+    );
+}
+
+#[test]
+fn parse_protocol_version() {
+    let data_sources = parse_test_file(
+        "- name: loki
+  providerType: loki
+  protocolVersion: 1
+  config: {}
+",
+    )
+    .unwrap();
+    assert_eq!(data_sources[0].protocol_version, Some(1));
+
+    match parse_test_file(
+        "- name: loki
+  providerType: loki
+  protocolVersion: 3
+  config: {}
+",
+    )
+    .unwrap_err()
+    {
+        Error::InvalidDataSource { field, message, .. } => {
+            assert_eq!(field.as_deref(), Some("protocolVersion"));
+            assert_eq!(message, "unsupported protocol version 3, expected 1 or 2");
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
+
+#[test]
+fn rejects_zero_concurrent_queries() {
+    let data_sources = parse_test_file(
+        "- name: loki
+  providerType: loki
+  maxConcurrentQueries: 2
+  config: {}
+",
+    )
+    .unwrap();
+    assert_eq!(data_sources[0].max_concurrent_queries, Some(2));
+
+    match parse_test_file(
+        "- name: loki
+  providerType: loki
+  maxConcurrentQueries: 0
+  config: {}
+",
+    )
+    .unwrap_err()
+    {
+        Error::InvalidDataSource { field, message, .. } => {
+            assert_eq!(field.as_deref(), Some("maxConcurrentQueries"));
+            assert_eq!(
+                message,
+                "the maximum number of concurrent queries must be greater than 0"
+            );
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+}
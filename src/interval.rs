@@ -0,0 +1,82 @@
+//! Durations given as a number of seconds, minutes or hours, like "30s",
+//! "5m" or "1h"
+
+use anyhow::{anyhow, Error};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{str::FromStr, time::Duration};
+
+/// Longest interval accepted, so that adding it to an instant never overflows
+pub const MAX_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntervalDuration(pub Duration);
+
+impl FromStr for IntervalDuration {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow!("expected a number of seconds, minutes or hours (for example \"30s\", \"5m\" or \"1h\")")
+        };
+        if s.is_empty() {
+            return Err(invalid());
+        }
+        let (value, unit) = s.split_at(s.len() - 1);
+        let unit_secs: u64 = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => return Err(invalid()),
+        };
+        let value = u64::from_str(value).map_err(|_| invalid())?;
+        match value.checked_mul(unit_secs).map(Duration::from_secs) {
+            Some(duration) if duration <= MAX_INTERVAL => Ok(IntervalDuration(duration)),
+            _ => Err(anyhow!(
+                "the interval can't be longer than {}h (365 days)",
+                MAX_INTERVAL.as_secs() / 3600
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for IntervalDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let interval = String::deserialize(deserializer)?;
+        IntervalDuration::from_str(&interval)
+            .map_err(|err| de::Error::custom(format!("invalid interval '{interval}', {err}")))
+    }
+}
+
+impl Serialize for IntervalDuration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(&format_args!("{}s", self.0.as_secs()))
+    }
+}
+
+#[test]
+fn interval_parsing() {
+    assert_eq!(
+        IntervalDuration(Duration::from_secs(30)),
+        "30s".parse().unwrap()
+    );
+    assert_eq!(
+        IntervalDuration(Duration::from_secs(60)),
+        "1m".parse().unwrap()
+    );
+    assert_eq!(
+        IntervalDuration(Duration::from_secs(3600)),
+        "1h".parse().unwrap()
+    );
+    IntervalDuration::from_str("3d").expect_err("invalid interval");
+    IntervalDuration::from_str("").expect_err("empty interval");
+    assert_eq!(IntervalDuration(MAX_INTERVAL), "8760h".parse().unwrap());
+    IntervalDuration::from_str("8761h").expect_err("interval too long");
+    IntervalDuration::from_str("18446744073709551615s").expect_err("interval too long");
+    IntervalDuration::from_str("18446744073709551615h").expect_err("overflowing interval");
+}
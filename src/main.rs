@@ -1,22 +1,26 @@
 pub mod cli;
+pub mod data_sources;
+pub mod interval;
 pub mod runtime;
 pub mod tasks;
 
 use anyhow::{anyhow, bail};
 use clap::Parser;
-use std::{io, path::PathBuf, process, str::FromStr};
-use tasks::service::{ProxyDataSource, ProxyService};
-use tokio::fs;
+use fiberplane::models::proxies::ProxyToken;
+use ring::signature::KeyPair;
+use std::{io, process, str::FromStr};
+use tasks::provider_manager::InstallMode;
+use tasks::service::{HttpServerConfig, ProxyService, QueryLimits};
 use tracing::{error, info, trace, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let args = cli::Arguments::parse();
+    let mut args = cli::Arguments::parse();
 
     initialize_logger(&args);
 
-    if let Some(subcommand) = args.subcommand {
+    if let Some(subcommand) = args.subcommand.take() {
         match subcommand {
             cli::Action::Config { action } => match action {
                 cli::ConfigAction::Paths { query } => {
@@ -25,6 +29,10 @@ async fn main() -> Result<(), anyhow::Error> {
                             "Expected location for 'data_sources.yaml': {:?}",
                             runtime::data_sources_path()?
                         );
+                        println!(
+                            "Alternative directory for data sources files: {:?}",
+                            runtime::data_sources_dir()?
+                        );
                         println!(
                             "Expected directory for providers: {:?}",
                             runtime::providers_wasm_dir()?
@@ -36,101 +44,281 @@ async fn main() -> Result<(), anyhow::Error> {
                         cli::ConfigPathQuery::DataSources => {
                             print!("{}", runtime::data_sources_path()?.display())
                         }
+                        cli::ConfigPathQuery::DataSourcesDir => {
+                            print!("{}", runtime::data_sources_dir()?.display())
+                        }
                         cli::ConfigPathQuery::WasmDir => {
                             print!("{}", runtime::providers_wasm_dir()?.display())
                         }
                     }
                     return Ok(());
                 }
+                cli::ConfigAction::Validate => {
+                    let data_sources_path =
+                        runtime::resolve_data_sources_path(args.data_sources_path)?;
+                    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                    let report =
+                        tasks::config_validation::validate(&data_sources_path, &wasm_dir).await?;
+                    print!("{report}");
+                    if report.has_errors() {
+                        bail!(
+                            "The data sources in {} are invalid",
+                            data_sources_path.display()
+                        );
+                    }
+                    return Ok(());
+                }
+            },
+            cli::Action::Pull {
+                names,
+                all,
+                update,
+                force,
+                registry,
+                public_key,
+                allow_unverified,
+            } => {
+                let mode = install_mode(update, force);
+                let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                tasks::provider_manager::pull(
+                    &wasm_dir,
+                    &registry,
+                    names.as_slice(),
+                    all,
+                    mode,
+                    public_key.as_deref(),
+                    allow_unverified,
+                )
+                .await?;
+                return Ok(());
+            }
+            cli::Action::Providers { action } => match action {
+                cli::ProvidersAction::List { output } => {
+                    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                    let data_sources_path =
+                        runtime::resolve_data_sources_path(args.data_sources_path)?;
+                    let inventory =
+                        tasks::provider_inventory::list(&wasm_dir, &data_sources_path).await?;
+                    match output {
+                        cli::OutputFormat::Table => print!("{inventory}"),
+                        cli::OutputFormat::Json => {
+                            println!("{}", serde_json::to_string_pretty(&inventory)?)
+                        }
+                    }
+                    return Ok(());
+                }
+                cli::ProvidersAction::Inspect {
+                    provider,
+                    config,
+                    output,
+                } => {
+                    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                    let path = tasks::provider_inspection::provider_module(&wasm_dir, &provider);
+                    let description = tasks::provider_inspection::inspect(&path, config.0).await?;
+                    match output {
+                        cli::OutputFormat::Table => print!("{description}"),
+                        cli::OutputFormat::Json => {
+                            println!("{}", serde_json::to_string_pretty(&description)?)
+                        }
+                    }
+                    return Ok(());
+                }
+                cli::ProvidersAction::Bundle {
+                    output,
+                    signing_key,
+                } => {
+                    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                    let signing_key = signing_key
+                        .as_deref()
+                        .map(tasks::provider_manager::parse_signing_key)
+                        .transpose()?;
+                    let manifest =
+                        tasks::provider_manager::bundle(&wasm_dir, &output, signing_key.as_ref())
+                            .await?;
+                    println!(
+                        "Bundled {} providers into {}",
+                        manifest.providers.len(),
+                        output.display()
+                    );
+                    if let Some(signing_key) = signing_key {
+                        println!(
+                            "Signed the manifest, check it with --public-key {}",
+                            base64::encode(signing_key.public_key())
+                        );
+                    }
+                    return Ok(());
+                }
+                cli::ProvidersAction::InstallBundle {
+                    bundle,
+                    update,
+                    force,
+                    public_key,
+                } => {
+                    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                    let manifest = tasks::provider_manager::install_bundle(
+                        &wasm_dir,
+                        &bundle,
+                        install_mode(update, force),
+                        public_key.as_deref(),
+                    )
+                    .await?;
+                    println!(
+                        "Installed the providers of {} ({}) into {}",
+                        bundle.display(),
+                        manifest
+                            .providers
+                            .iter()
+                            .map(|provider| provider.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        wasm_dir.display()
+                    );
+                    return Ok(());
+                }
             },
-            cli::Action::Pull { names, all } => {
-                tasks::provider_manager::pull(names.as_slice(), all).await?;
+            cli::Action::Status { output } => {
+                let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir.clone())?;
+                let data_sources_path =
+                    runtime::resolve_data_sources_path(args.data_sources_path.clone())?;
+                let report =
+                    tasks::status_report::check(&wasm_dir, &data_sources_path, query_limits(&args))
+                        .await?;
+                match output {
+                    cli::OutputFormat::Table => print!("{report}"),
+                    cli::OutputFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&report)?)
+                    }
+                }
+                if report.failed() > 0 {
+                    bail!(
+                        "{} data source(s) failed their status check",
+                        report.failed()
+                    );
+                }
+                return Ok(());
+            }
+            cli::Action::Doctor { output, api_base } => {
+                let query_limits = query_limits(&args);
+                let diagnosis = tasks::doctor::diagnose(tasks::doctor::DoctorOptions {
+                    wasm_dir: args.wasm_dir,
+                    data_sources_path: args.data_sources_path,
+                    token: args.token,
+                    api_base: api_base.unwrap_or(args.api_base),
+                    query_limits,
+                })
+                .await;
+                match output {
+                    cli::OutputFormat::Table => print!("{diagnosis}"),
+                    cli::OutputFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&diagnosis)?)
+                    }
+                }
+                if diagnosis.failed() > 0 {
+                    bail!("{} check(s) failed", diagnosis.failed());
+                }
+                return Ok(());
+            }
+            cli::Action::Query {
+                data_source,
+                query_type,
+                query_data,
+                mime_type,
+                cells,
+                output,
+            } => {
+                let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir)?;
+                let data_sources_path = runtime::resolve_data_sources_path(args.data_sources_path)?;
+                let result = tasks::local_query::run(
+                    &wasm_dir,
+                    &data_sources_path,
+                    tasks::local_query::LocalQuery {
+                        data_source,
+                        query_type,
+                        query_data,
+                        mime_type,
+                        create_cells: cells,
+                    },
+                    Some(args.query_timeout.0).filter(|timeout| !timeout.is_zero()),
+                )
+                .await?;
+                match output {
+                    cli::OutputFormat::Table => print!("{result}"),
+                    cli::OutputFormat::Json => {
+                        println!("{}", serde_json::to_string_pretty(&result)?)
+                    }
+                }
                 return Ok(());
             }
         }
     }
 
-    let wasm_dir = {
-        if args.wasm_dir.is_some() {
-            args.wasm_dir.clone().unwrap()
-        } else if PathBuf::from_str("./providers")
-            .map_or(false, |path| path.exists() && path.is_dir())
-        {
-            PathBuf::from_str("./providers").unwrap()
-        } else {
-            runtime::providers_wasm_dir()?
-        }
-    };
+    let wasm_dir = runtime::resolve_wasm_dir(args.wasm_dir.clone())?;
 
     if !wasm_dir.is_dir() {
         bail!("wasm_dir ({wasm_dir:?}) must be a directory");
     }
 
-    let data_sources_path = {
-        if args.data_sources_path.is_some() {
-            args.data_sources_path.clone().unwrap()
-        } else if PathBuf::from_str("./data_sources.yaml")
-            .map_or(false, |path| path.exists() && path.is_file())
-        {
-            PathBuf::from_str("./data_sources.yaml").unwrap()
-        } else {
-            runtime::data_sources_path()?
-        }
-    };
+    let data_sources_path = runtime::resolve_data_sources_path(args.data_sources_path.clone())?;
 
-    // Load data sources config file
-    let data_sources = {
-        match fs::read_to_string(&data_sources_path).await {
-            Ok(data_sources) => data_sources,
-            Err(err) => {
-                match err.kind() {
-                    io::ErrorKind::NotFound => {
-                        bail!(
-                            "Data sources file not found at {} ({})",
-                            data_sources_path.display(),
-                            err
-                        );
-                    }
-                    io::ErrorKind::PermissionDenied => {
-                        bail!(
-                            "Insufficient permissions to read data sources file {} ({})",
-                            data_sources_path.display(),
-                            err
-                        );
-                    }
-                    _ => {
-                        bail!(
-                            "Unable to read data sources file at {}: {}",
-                            data_sources_path.display(),
-                            err
-                        );
-                    }
-                };
-            }
-        }
-    };
-
-    let data_sources: Vec<ProxyDataSource> =
-        serde_yaml::from_str(&data_sources).expect("Invalid 'data_sources.yaml' file");
+    // Load data sources config files
+    let data_sources_files = data_sources::read(&data_sources_path).await?;
+    let data_sources = data_sources::parse(&data_sources_files)?;
 
-    let proxy = ProxyService::init(
-        args.api_base,
-        args.token.ok_or_else(|| {
+    let query_limits = query_limits(&args);
+    if args.local_api && args.listen_address.is_none() {
+        bail!("--local-api requires --listen-address");
+    }
+    let http_server = args.listen_address.map(|listen_address| HttpServerConfig {
+        listen_address,
+        local_api: args.local_api,
+        local_api_token: args.local_api_token.clone(),
+    });
+    if let Some(http_server) = &http_server {
+        http_server.check()?;
+    }
+    let token: ProxyToken = args
+        .token
+        .ok_or_else(|| {
             anyhow!(
                 "TOKEN is mandatory to run Fiberplane Daemon. See {} --help",
                 clap::crate_name!()
             )
-        })?,
+        })?
+        .parse()
+        .map_err(|err| {
+            anyhow!(
+                "Invalid TOKEN: {err}. See {} doctor to check the configuration",
+                clap::crate_name!()
+            )
+        })?;
+    let proxy = ProxyService::init(
+        args.api_base,
+        token,
         wasm_dir.as_path(),
-        data_sources,
+        data_sources.clone(),
         args.max_retries,
-        args.listen_address,
+        http_server,
         args.status_check_interval.0,
+        query_limits,
     )
     .await;
 
     let (shutdown, _) = tokio::sync::broadcast::channel(3);
 
+    tokio::spawn(tasks::config_watcher::watch_data_sources(
+        proxy.clone(),
+        data_sources_path,
+        data_sources,
+        args.data_sources_reload_interval.0,
+        shutdown.subscribe(),
+    ));
+
+    tokio::spawn(tasks::provider_watcher::watch_providers(
+        proxy.clone(),
+        args.providers_reload_interval.0,
+        shutdown.subscribe(),
+    ));
+
     let cloned_shutdown = shutdown.clone();
     ctrlc::set_handler(move || {
         info!("received SIGINT, shutting down listeners");
@@ -153,6 +341,26 @@ async fn main() -> Result<(), anyhow::Error> {
     }
 }
 
+fn query_limits(args: &cli::Arguments) -> QueryLimits {
+    QueryLimits {
+        timeout: Some(args.query_timeout.0).filter(|timeout| !timeout.is_zero()),
+        max_concurrent_queries: args.max_concurrent_queries,
+        max_concurrent_queries_per_data_source: args.max_concurrent_queries_per_data_source,
+        max_queued_queries: args.max_queued_queries,
+        max_blocking_calls: args.max_blocking_calls,
+    }
+}
+
+fn install_mode(update: bool, force: bool) -> InstallMode {
+    if force {
+        InstallMode::Force
+    } else if update {
+        InstallMode::Update
+    } else {
+        InstallMode::Missing
+    }
+}
+
 fn initialize_logger(args: &cli::Arguments) {
     let env_filter = if let Some(rust_log) = &args.rust_log {
         EnvFilter::from_str(rust_log).expect("Invalid RUST_LOG value")
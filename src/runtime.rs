@@ -1,7 +1,8 @@
 //! Utility functions that allow runtime configuration behaviour
 
 use directories::ProjectDirs;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -12,6 +13,27 @@ pub enum Error {
     ProvidersDirUnavailable(PathBuf),
 }
 
+/// Where a configuration path comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathOrigin {
+    /// Given on the command line or with an environment variable
+    Argument,
+    /// Found in the working directory, as in a development setup
+    WorkingDirectory,
+    /// The canonical location of the platform
+    ProjectDirs,
+}
+
+impl fmt::Display for PathOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathOrigin::Argument => "given as an argument or environment variable",
+            PathOrigin::WorkingDirectory => "found in the working directory",
+            PathOrigin::ProjectDirs => "default location of the platform",
+        })
+    }
+}
+
 pub const QUALIFIER: &str = "dev";
 pub const ORGANIZATION_NAME: &str = "fiberplane";
 pub const APP_NAME: &str = clap::crate_name!();
@@ -23,9 +45,71 @@ pub fn data_sources_path() -> Result<PathBuf, Error> {
     Ok(proj_dirs.config_dir().join("data_sources.yaml"))
 }
 
+/// Return the canonical directory to put data sources configuration files
+/// in, as an alternative to a single `data_sources.yaml` file
+pub fn data_sources_dir() -> Result<PathBuf, Error> {
+    let proj_dirs =
+        ProjectDirs::from(QUALIFIER, ORGANIZATION_NAME, APP_NAME).ok_or(Error::ProjDir)?;
+    Ok(proj_dirs.config_dir().join("data_sources.d"))
+}
+
 /// Return the canonical directory to put the wasm blobs for providers
 pub fn providers_wasm_dir() -> Result<PathBuf, Error> {
     let proj_dirs =
         ProjectDirs::from(QUALIFIER, ORGANIZATION_NAME, APP_NAME).ok_or(Error::ProjDir)?;
     Ok(proj_dirs.data_local_dir().join("providers"))
 }
+
+/// Return the directory containing the providers to use.
+///
+/// In order, this is the given directory, the local `./providers` directory when
+/// running from a development setup, or the canonical providers directory.
+pub fn resolve_wasm_dir(wasm_dir: Option<PathBuf>) -> Result<PathBuf, Error> {
+    locate_wasm_dir(wasm_dir).map(|(path, _)| path)
+}
+
+/// Like [resolve_wasm_dir], also returning where the directory comes from
+pub fn locate_wasm_dir(wasm_dir: Option<PathBuf>) -> Result<(PathBuf, PathOrigin), Error> {
+    match wasm_dir {
+        Some(wasm_dir) => Ok((wasm_dir, PathOrigin::Argument)),
+        None if Path::new("./providers").is_dir() => {
+            Ok((PathBuf::from("./providers"), PathOrigin::WorkingDirectory))
+        }
+        None => Ok((providers_wasm_dir()?, PathOrigin::ProjectDirs)),
+    }
+}
+
+/// Return the path to the data sources configuration to use, which is either
+/// a file or a directory of files.
+///
+/// In order, this is the given path, the local `./data_sources.yaml` file or
+/// `./data_sources.d` directory when running from a development setup, or the
+/// canonical configuration file, falling back to the canonical configuration
+/// directory if only that one exists.
+pub fn resolve_data_sources_path(path: Option<PathBuf>) -> Result<PathBuf, Error> {
+    locate_data_sources_path(path).map(|(path, _)| path)
+}
+
+/// Like [resolve_data_sources_path], also returning where the path comes from
+pub fn locate_data_sources_path(path: Option<PathBuf>) -> Result<(PathBuf, PathOrigin), Error> {
+    match path {
+        Some(path) => Ok((path, PathOrigin::Argument)),
+        None if Path::new("./data_sources.yaml").is_file() => Ok((
+            PathBuf::from("./data_sources.yaml"),
+            PathOrigin::WorkingDirectory,
+        )),
+        None if Path::new("./data_sources.d").is_dir() => Ok((
+            PathBuf::from("./data_sources.d"),
+            PathOrigin::WorkingDirectory,
+        )),
+        None => {
+            let path = data_sources_path()?;
+            let dir = data_sources_dir()?;
+            if !path.exists() && dir.is_dir() {
+                Ok((dir, PathOrigin::ProjectDirs))
+            } else {
+                Ok((path, PathOrigin::ProjectDirs))
+            }
+        }
+    }
+}
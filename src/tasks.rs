@@ -1,6 +1,15 @@
+pub mod config_validation;
+pub mod config_watcher;
+pub mod doctor;
+pub mod local_query;
 pub mod metrics;
+pub mod provider_inspection;
+pub mod provider_inventory;
 pub mod provider_manager;
+pub mod provider_watcher;
 pub mod service;
+pub mod status_report;
+mod table;
 pub mod tokio_tungstenite_reconnect;
 
 #[cfg(test)]
@@ -0,0 +1,265 @@
+//! Validation of the data sources configuration against the config schema
+//! published by their providers
+
+use super::service::{bindings, load_wasm_modules};
+use crate::data_sources;
+use anyhow::Result;
+use fiberplane::models::names::Name;
+use fiberplane::provider_bindings::ConfigField;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// Diagnostics found for a single data source
+#[derive(Debug)]
+pub struct DataSourceReport {
+    pub name: Name,
+    pub provider_type: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Result of the validation of all data sources, in configuration order
+#[derive(Debug)]
+pub struct Report {
+    pub data_sources: Vec<DataSourceReport>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.data_sources
+            .iter()
+            .flat_map(|data_source| &data_source.diagnostics)
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for data_source in &self.data_sources {
+            if data_source.diagnostics.is_empty() {
+                writeln!(
+                    f,
+                    "{} ({}): ok",
+                    data_source.name, data_source.provider_type
+                )?;
+                continue;
+            }
+            writeln!(f, "{} ({}):", data_source.name, data_source.provider_type)?;
+            for diagnostic in &data_source.diagnostics {
+                let severity = match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                writeln!(f, "  {severity}: {}", diagnostic.message)?;
+            }
+        }
+        writeln!(
+            f,
+            "\nChecked {} data sources: {} error(s), {} warning(s)",
+            self.data_sources.len(),
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        )
+    }
+}
+
+/// Load the data sources at `data_sources_path` and the providers they use from
+/// `wasm_dir`, and check the config of every data source against the config
+/// schema of its provider.
+pub async fn validate(data_sources_path: &Path, wasm_dir: &Path) -> Result<Report> {
+    let files = data_sources::read(data_sources_path).await?;
+    let data_sources = data_sources::parse(&files)?;
+
+    let modules: HashSet<PathBuf> = data_sources
+        .iter()
+        .map(|data_source| data_source.provider_module(wasm_dir))
+        .collect();
+    let wasm_modules = load_wasm_modules(modules.into_iter().collect()).await;
+
+    let data_sources = data_sources
+        .into_iter()
+        .map(|data_source| {
+            let module_path = data_source.provider_module(wasm_dir);
+            let diagnostics = match wasm_modules.get(&module_path) {
+                Some(Ok(_)) if data_source.protocol_version(wasm_dir, &wasm_modules) == 1 => {
+                    vec![Diagnostic::warning(
+                        "the provider uses protocol v1, which has no config schema: config not checked",
+                    )]
+                }
+                Some(Ok(module)) if !module.has_config_schema => vec![Diagnostic::warning(
+                    "provider has no config schema: config not checked",
+                )],
+                Some(Ok(module)) => match bindings::get_config_schema(&module.runtime) {
+                    Ok(schema) => check_config(&schema, &data_source.config),
+                    Err(err) => vec![Diagnostic::error(format!(
+                        "unable to get the config schema of the provider: {err}"
+                    ))],
+                },
+                Some(Err(err)) => vec![Diagnostic::error(format!(
+                    "unable to load the '{}' provider: {err}",
+                    data_source.provider_type
+                ))],
+                None => vec![Diagnostic::error(format!(
+                    "the provider module {} wasn't loaded",
+                    module_path.display()
+                ))],
+            };
+            DataSourceReport {
+                name: data_source.name,
+                provider_type: data_source.provider_type,
+                diagnostics,
+            }
+        })
+        .collect();
+
+    Ok(Report { data_sources })
+}
+
+/// Check a data source config against the config schema of its provider
+fn check_config(schema: &[ConfigField], config: &Map<String, Value>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut known_fields = HashSet::new();
+
+    for field in schema {
+        let (name, required) = match field {
+            ConfigField::Checkbox(field) => (&field.name, field.required),
+            ConfigField::Integer(field) => (&field.name, field.required),
+            ConfigField::Select(field) => (&field.name, field.required),
+            ConfigField::Text(field) => (&field.name, field.required),
+        };
+        known_fields.insert(name.as_str());
+
+        match config.get(name) {
+            None | Some(Value::Null) => {
+                if required {
+                    diagnostics.push(Diagnostic::error(format!(
+                        "missing required field '{name}'"
+                    )));
+                }
+            }
+            Some(Value::String(value)) if value.is_empty() && required => {
+                diagnostics.push(Diagnostic::error(format!(
+                    "required field '{name}' is empty"
+                )));
+            }
+            Some(value) => {
+                if let Some(problem) = check_field_value(field, value) {
+                    diagnostics.push(Diagnostic::error(format!("field '{name}' {problem}")));
+                }
+            }
+        }
+    }
+
+    for key in config.keys() {
+        if !known_fields.contains(key.as_str()) {
+            diagnostics.push(Diagnostic::warning(format!(
+                "unknown field '{key}', the provider will ignore it"
+            )));
+        }
+    }
+
+    diagnostics
+}
+
+/// Return a description of the problem if the value doesn't match the field
+fn check_field_value(field: &ConfigField, value: &Value) -> Option<String> {
+    let multiple = match field {
+        ConfigField::Select(field) => field.multiple,
+        ConfigField::Text(field) => field.multiple,
+        _ => false,
+    };
+    match value {
+        Value::Array(values) if multiple => values
+            .iter()
+            .find_map(|value| check_single_value(field, value)),
+        value => check_single_value(field, value),
+    }
+}
+
+fn check_single_value(field: &ConfigField, value: &Value) -> Option<String> {
+    match field {
+        ConfigField::Text(_) if !value.is_string() => {
+            Some(format!("must be a string, found {}", describe(value)))
+        }
+        ConfigField::Checkbox(_) if !value.is_boolean() => Some(format!(
+            "must be a boolean (true or false), found {}",
+            describe(value)
+        )),
+        ConfigField::Integer(field) => match value.as_i64() {
+            None => Some(format!("must be an integer, found {}", describe(value))),
+            Some(value) => match (field.min.map(i64::from), field.max.map(i64::from)) {
+                (Some(min), _) if value < min => {
+                    Some(format!("must be at least {min}, found {value}"))
+                }
+                (_, Some(max)) if value > max => {
+                    Some(format!("must be at most {max}, found {value}"))
+                }
+                _ => None,
+            },
+        },
+        ConfigField::Select(field) => match value.as_str() {
+            None => Some(format!("must be a string, found {}", describe(value))),
+            Some(value) => {
+                if field.options.is_empty() || field.options.iter().any(|option| option == value) {
+                    None
+                } else {
+                    Some(format!(
+                        "must be one of '{}', found '{value}'",
+                        field.options.join("', '")
+                    ))
+                }
+            }
+        },
+        _ => None,
+    }
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "nothing",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "a list",
+        Value::Object(_) => "a map",
+    }
+}
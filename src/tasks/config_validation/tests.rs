@@ -0,0 +1,116 @@
+use super::{check_config, Diagnostic, Severity};
+use fiberplane::provider_bindings::{
+    CheckboxField, ConfigField, IntegerField, SelectField, TextField,
+};
+use serde_json::json;
+
+fn text(name: &str, required: bool) -> ConfigField {
+    let mut field = TextField::default();
+    field.name = name.to_string();
+    field.required = required;
+    ConfigField::Text(field)
+}
+
+fn select(name: &str, multiple: bool, options: &[&str]) -> ConfigField {
+    let mut field = SelectField::default();
+    field.name = name.to_string();
+    field.multiple = multiple;
+    field.options = options.iter().map(ToString::to_string).collect();
+    ConfigField::Select(field)
+}
+
+fn schema() -> Vec<ConfigField> {
+    let mut timeout = IntegerField::default();
+    timeout.name = "timeout".to_string();
+    timeout.min = Some(1);
+    timeout.max = Some(300);
+    let mut insecure = CheckboxField::default();
+    insecure.name = "insecure".to_string();
+
+    vec![
+        text("url", true),
+        text("token", false),
+        ConfigField::Integer(timeout),
+        ConfigField::Checkbox(insecure),
+        select("mode", false, &["fast", "exact"]),
+        select("indices", true, &["logs", "metrics"]),
+    ]
+}
+
+fn messages(diagnostics: Vec<Diagnostic>) -> Vec<(Severity, String)> {
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| (diagnostic.severity, diagnostic.message))
+        .collect()
+}
+
+#[test]
+fn accepts_valid_config() {
+    let config = json!({
+        "url": "http://localhost:9090",
+        "timeout": 30,
+        "insecure": false,
+        "mode": "exact",
+        "indices": ["logs", "metrics"]
+    });
+    assert_eq!(
+        check_config(&schema(), config.as_object().unwrap()),
+        Vec::new()
+    );
+}
+
+#[test]
+fn reports_missing_required_fields() {
+    let config = json!({ "token": "secret" });
+    assert_eq!(
+        messages(check_config(&schema(), config.as_object().unwrap())),
+        vec![(Severity::Error, "missing required field 'url'".to_string())]
+    );
+
+    let config = json!({ "url": "" });
+    assert_eq!(
+        messages(check_config(&schema(), config.as_object().unwrap())),
+        vec![(Severity::Error, "required field 'url' is empty".to_string())]
+    );
+}
+
+#[test]
+fn reports_invalid_types_and_unknown_fields() {
+    let config = json!({
+        "url": 9090,
+        "timeout": 600,
+        "insecure": "yes",
+        "mode": "slow",
+        "indices": ["logs", "traces"],
+        "uri": "http://localhost:9090"
+    });
+    assert_eq!(
+        messages(check_config(&schema(), config.as_object().unwrap())),
+        vec![
+            (
+                Severity::Error,
+                "field 'url' must be a string, found a number".to_string()
+            ),
+            (
+                Severity::Error,
+                "field 'timeout' must be at most 300, found 600".to_string()
+            ),
+            (
+                Severity::Error,
+                "field 'insecure' must be a boolean (true or false), found a string".to_string()
+            ),
+            (
+                Severity::Error,
+                "field 'mode' must be one of 'fast', 'exact', found 'slow'".to_string()
+            ),
+            (
+                Severity::Error,
+                "field 'indices' must be one of 'logs', 'metrics', found 'traces'".to_string()
+            ),
+            (
+                Severity::Warning,
+                "unknown field 'uri', the provider will ignore it".to_string()
+            ),
+        ]
+    );
+}
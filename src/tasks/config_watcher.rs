@@ -0,0 +1,123 @@
+//! Task to reload the data sources when their configuration changes
+
+use super::service::{ProxyDataSource, ProxyService};
+use crate::data_sources;
+use futures::{future::pending, select, FutureExt};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Notify};
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+/// Reload the data sources of the service whenever they change, or when the
+/// process receives SIGHUP.
+///
+/// The file (or the files in the directory) at `path` is parsed again on every
+/// poll, which also resolves the environment variables and secret files of the
+/// configs again: a rotated secret file is picked up like a change of the data
+/// sources file. The files are parsed on a blocking thread, as resolving the
+/// secret files reads them synchronously.
+///
+/// `data_sources` are the data sources the service was initialized with.
+/// A zero `poll_interval` disables polling the file, leaving only SIGHUP to
+/// trigger a reload.
+pub async fn watch_data_sources(
+    service: ProxyService,
+    path: PathBuf,
+    mut data_sources: Vec<ProxyDataSource>,
+    poll_interval: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let reload = Arc::new(Notify::new());
+    forward_hangup_signals(reload.clone());
+
+    let mut poll_interval = if poll_interval.is_zero() {
+        None
+    } else {
+        let mut poll_interval = interval(poll_interval);
+        poll_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Some(poll_interval)
+    };
+    // Only log an invalid configuration once, until it changes
+    let mut last_error = None;
+
+    loop {
+        let forced = select! {
+            _ = tick(&mut poll_interval).fuse() => false,
+            _ = reload.notified().fuse() => true,
+            _ = shutdown.recv().fuse() => break,
+        };
+
+        let files = match data_sources::read(&path).await {
+            Ok(files) => files,
+            Err(err) => {
+                // The file can briefly disappear while it gets replaced (for example
+                // when a Kubernetes ConfigMap is updated), so only log the error.
+                warn!("Unable to reload data sources: {err:#}");
+                continue;
+            }
+        };
+
+        let parsed = match spawn_blocking(move || data_sources::parse(&files)).await {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                error!("Unable to reload data sources: {err}");
+                continue;
+            }
+        };
+        match parsed {
+            Ok(new_data_sources) => {
+                last_error = None;
+                if !forced && new_data_sources == data_sources {
+                    continue;
+                }
+                data_sources = new_data_sources;
+                info!(
+                    "Reloading {} data sources from {}",
+                    data_sources.len(),
+                    path.display()
+                );
+                service.reload_data_sources(data_sources.clone()).await;
+            }
+            Err(err) => {
+                let err = format!("{err:#}");
+                if forced || last_error.as_ref() != Some(&err) {
+                    error!(
+                        "Keeping the current data sources, the new configuration is invalid: {err}"
+                    );
+                }
+                last_error = Some(err);
+            }
+        }
+    }
+    debug!("stopped watching data sources");
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => pending().await,
+    }
+}
+
+#[cfg(unix)]
+fn forward_hangup_signals(reload: Arc<Notify>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    info!("received SIGHUP, reloading data sources");
+                    reload.notify_one();
+                }
+            });
+        }
+        Err(err) => error!(?err, "unable to listen for SIGHUP"),
+    }
+}
+
+#[cfg(not(unix))]
+fn forward_hangup_signals(_reload: Arc<Notify>) {}
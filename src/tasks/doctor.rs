@@ -0,0 +1,468 @@
+//! End-to-end diagnostics of the setup of the daemon: where its configuration
+//! is found, whether it loads, whether it can connect to Fiberplane and reach
+//! its data sources
+
+use super::service::{load_data_sources, ProxyDataSource, ProxyService, QueryLimits, WasmModules};
+use super::status_report::{check_data_sources, HealthStatus};
+use crate::data_sources::{self, DataSourcesFile};
+use crate::runtime::{self, PathOrigin};
+use fiberplane::models::proxies::ProxyToken;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::{fmt, time::Duration};
+use tokio_tungstenite::tungstenite;
+use url::Url;
+
+#[cfg(test)]
+mod tests;
+
+/// Maximum duration of the connection to the relay
+const RELAY_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStatus {
+    Pass,
+    /// The check passed, but something may not work as expected
+    Warn,
+    Fail,
+    /// The check couldn't run because of an earlier failure
+    Skip,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "SKIP",
+        })
+    }
+}
+
+/// Outcome of one of the checks, with a hint to fix it if it didn't pass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn skip(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status: CheckStatus::Skip,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+}
+
+/// The checks, in the order they ran
+#[derive(Debug, Serialize)]
+pub struct Diagnosis {
+    pub checks: Vec<Check>,
+}
+
+impl Diagnosis {
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+            .count()
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "[{}] {}: {}", check.status, check.name, check.detail)?;
+            if let Some(hint) = &check.hint {
+                writeln!(f, "       hint: {hint}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the daemon was given to run with
+#[derive(Debug, Clone)]
+pub struct DoctorOptions {
+    pub wasm_dir: Option<PathBuf>,
+    pub data_sources_path: Option<PathBuf>,
+    /// The token, as given (it may not parse)
+    pub token: Option<String>,
+    pub api_base: Url,
+    pub query_limits: QueryLimits,
+}
+
+/// Run the checks in turn. Checks that depend on a failed check are skipped.
+pub async fn diagnose(options: DoctorOptions) -> Diagnosis {
+    let mut checks = Vec::new();
+
+    let data_sources_path = check_data_sources_path(options.data_sources_path, &mut checks);
+    let wasm_dir = check_wasm_dir(options.wasm_dir, &mut checks);
+
+    let files = match &data_sources_path {
+        Some(path) => check_files(path, &mut checks).await,
+        None => {
+            checks.push(Check::skip(
+                "Data sources files",
+                "no data sources path to read",
+            ));
+            None
+        }
+    };
+    let data_sources = match &files {
+        Some(files) => match data_sources::parse(files) {
+            Ok(data_sources) => {
+                checks.push(Check::pass(
+                    "Data sources YAML",
+                    format!("{} data source(s) defined", data_sources.len()),
+                ));
+                Some(data_sources)
+            }
+            Err(err) => {
+                checks.push(Check::fail(
+                    "Data sources YAML",
+                    err.to_string(),
+                    "fix the data sources at the position above; `fpd config validate` also checks their config against their providers",
+                ));
+                None
+            }
+        },
+        None => {
+            checks.push(Check::skip(
+                "Data sources YAML",
+                "no data sources file read",
+            ));
+            None
+        }
+    };
+
+    // The providers are loaded even if the wasm directory check failed, to
+    // report each missing provider
+    let service = match (&data_sources, &wasm_dir) {
+        (Some(data_sources), Some(wasm_dir)) => {
+            let (by_name, wasm_modules) = load_data_sources(wasm_dir, data_sources.clone()).await;
+            check_providers(wasm_dir, data_sources, &wasm_modules, &mut checks);
+            Some(ProxyService::local(
+                wasm_dir,
+                wasm_modules,
+                by_name,
+                options.query_limits,
+            ))
+        }
+        _ => {
+            checks.push(Check::skip("Providers", "no data sources loaded"));
+            None
+        }
+    };
+
+    let token = check_token(options.token.as_deref(), &mut checks);
+    match token {
+        Some(token) => {
+            let relay = ProxyService::new(
+                options.api_base,
+                token,
+                &wasm_dir.unwrap_or_default(),
+                WasmModules::new(),
+                HashMap::new(),
+                0,
+                None,
+                Duration::from_secs(300),
+                Default::default(),
+            );
+            checks.push(check_relay(&relay).await);
+        }
+        None => checks.push(Check::skip("Relay connection", "no valid token")),
+    }
+
+    match (&service, &data_sources) {
+        (Some(service), Some(data_sources)) => {
+            for health in check_data_sources(service, data_sources).await {
+                let name = format!("Data source {}", health.name);
+                checks.push(match health.status {
+                    HealthStatus::Connected => Check::pass(
+                        name,
+                        format!("connected in {}ms", health.latency_ms.unwrap_or_default()),
+                    ),
+                    HealthStatus::Skipped => Check::skip(name, "status checks are disabled"),
+                    HealthStatus::Error => Check::fail(
+                        name,
+                        health.error.unwrap_or_default(),
+                        format!(
+                            "check the config of the data source, and that this host can reach it; `fpd query {} <query type>` shows the errors of the {} provider",
+                            health.name, health.provider_type
+                        ),
+                    ),
+                });
+            }
+        }
+        _ => checks.push(Check::skip("Data sources status", "no data sources loaded")),
+    }
+
+    Diagnosis { checks }
+}
+
+fn check_data_sources_path(path: Option<PathBuf>, checks: &mut Vec<Check>) -> Option<PathBuf> {
+    const NAME: &str = "Data sources path";
+    match runtime::locate_data_sources_path(path) {
+        Ok((path, origin)) if path.exists() => {
+            checks.push(Check::pass(NAME, describe_path(&path, origin)));
+            Some(path)
+        }
+        Ok((path, origin)) => {
+            checks.push(Check::fail(
+                NAME,
+                format!("{} doesn't exist", describe_path(&path, origin)),
+                "create it, or set --data-sources-path (or DATA_SOURCES_PATH); `fpd config paths` prints where the daemon looks for it",
+            ));
+            None
+        }
+        Err(err) => {
+            checks.push(Check::fail(
+                NAME,
+                err.to_string(),
+                "set --data-sources-path (or DATA_SOURCES_PATH)",
+            ));
+            None
+        }
+    }
+}
+
+fn check_wasm_dir(wasm_dir: Option<PathBuf>, checks: &mut Vec<Check>) -> Option<PathBuf> {
+    const NAME: &str = "Wasm directory";
+    match runtime::locate_wasm_dir(wasm_dir) {
+        Ok((path, origin)) if path.is_dir() => {
+            checks.push(Check::pass(NAME, describe_path(&path, origin)));
+            Some(path)
+        }
+        Ok((path, origin)) => {
+            let problem = if path.exists() {
+                "isn't a directory"
+            } else {
+                "doesn't exist"
+            };
+            checks.push(Check::fail(
+                NAME,
+                format!("{} {problem}", describe_path(&path, origin)),
+                "run `fpd pull --all` to download the providers, or set --wasm-dir (or WASM_DIR)",
+            ));
+            Some(path)
+        }
+        Err(err) => {
+            checks.push(Check::fail(
+                NAME,
+                err.to_string(),
+                "set --wasm-dir (or WASM_DIR)",
+            ));
+            None
+        }
+    }
+}
+
+fn describe_path(path: &Path, origin: PathOrigin) -> String {
+    format!("{} ({origin})", path.display())
+}
+
+async fn check_files(path: &Path, checks: &mut Vec<Check>) -> Option<Vec<DataSourcesFile>> {
+    const NAME: &str = "Data sources files";
+    match data_sources::read(path).await {
+        Ok(files) => {
+            checks.push(Check::pass(
+                NAME,
+                format!("{} file(s) readable", files.len()),
+            ));
+            Some(files)
+        }
+        Err(err) => {
+            let hint = match err {
+                data_sources::Error::PermissionDenied { .. } => {
+                    "give the user running the daemon read access to the files (and to their directory)"
+                }
+                data_sources::Error::NotFound { .. } => {
+                    "check that the files exist, and that they aren't broken symbolic links"
+                }
+                _ => "check that the data sources path points to a YAML file or to a directory of YAML files",
+            };
+            checks.push(Check::fail(NAME, err.to_string(), hint));
+            None
+        }
+    }
+}
+
+/// Check that the module of every provider used by the data sources exists and
+/// compiles
+fn check_providers(
+    wasm_dir: &Path,
+    data_sources: &[ProxyDataSource],
+    wasm_modules: &WasmModules,
+    checks: &mut Vec<Check>,
+) {
+    // Data sources using each module, by module path
+    let mut users: BTreeMap<PathBuf, Vec<&ProxyDataSource>> = BTreeMap::new();
+    for data_source in data_sources {
+        users
+            .entry(data_source.provider_module(wasm_dir))
+            .or_default()
+            .push(data_source);
+    }
+
+    for (module, data_sources) in users {
+        let provider_type = &data_sources[0].provider_type;
+        let name = format!("Provider {provider_type}");
+        let used_by: Vec<String> = data_sources
+            .iter()
+            .map(|data_source| data_source.name.to_string())
+            .collect();
+        let used_by = used_by.join(", ");
+
+        if !module.is_file() {
+            checks.push(Check::fail(
+                name,
+                format!("{} doesn't exist (used by {used_by})", module.display()),
+                format!(
+                    "run `fpd pull {provider_type}` to download it, or fix the providerType, providerVersion or providerPath of the data sources"
+                ),
+            ));
+            continue;
+        }
+        checks.push(match wasm_modules.get(&module) {
+            Some(Ok(loaded)) => match loaded.protocol_version {
+                Some(version) => Check::pass(
+                    name,
+                    format!("{} (protocol v{version}, used by {used_by})", module.display()),
+                ),
+                None => Check {
+                    name,
+                    status: CheckStatus::Warn,
+                    detail: format!(
+                        "{} loads, but its protocol version is unknown (used by {used_by})",
+                        module.display()
+                    ),
+                    hint: Some(
+                        "set protocolVersion on its data sources if they don't work".to_string(),
+                    ),
+                },
+            },
+            Some(Err(err)) => Check::fail(
+                name,
+                format!("{}: {err}", module.display()),
+                format!("the module may be corrupted or incomplete: run `fpd pull --force {provider_type}` to download it again"),
+            ),
+            None => unreachable!("the providers of all the data sources are loaded"),
+        });
+    }
+}
+
+fn check_token(token: Option<&str>, checks: &mut Vec<Check>) -> Option<ProxyToken> {
+    const NAME: &str = "Token";
+    let token = match token {
+        Some(token) => token,
+        None => {
+            checks.push(Check::fail(
+                NAME,
+                "no token given",
+                "set --token (or TOKEN) to the token created with `fp daemon create` or in Studio",
+            ));
+            return None;
+        }
+    };
+    // The daemon parses the token as is, so whitespace around it isn't ignored
+    if token.trim() != token {
+        checks.push(Check::fail(
+            NAME,
+            "the token starts or ends with whitespace",
+            "remove the spaces or the newline around the token (a secret written with `echo` ends with a newline, use `echo -n`)",
+        ));
+        return None;
+    }
+    match token.parse::<ProxyToken>() {
+        Ok(token) => {
+            checks.push(Check::pass(
+                NAME,
+                format!(
+                    "workspace {}, daemon {}",
+                    token.workspace_id, token.proxy_name
+                ),
+            ));
+            Some(token)
+        }
+        Err(err) => {
+            checks.push(Check::fail(
+                NAME,
+                format!("unable to parse the token: {err}"),
+                "copy the whole token again, it may have been truncated; create a new one with `fp daemon create` if it was lost",
+            ));
+            None
+        }
+    }
+}
+
+async fn check_relay(relay: &ProxyService) -> Check {
+    const NAME: &str = "Relay connection";
+    let endpoint = relay
+        .relay_endpoint()
+        .map(Url::to_string)
+        .unwrap_or_default();
+    let host = relay
+        .relay_endpoint()
+        .and_then(Url::host_str)
+        .unwrap_or_default()
+        .to_string();
+
+    let err = match tokio::time::timeout(RELAY_TIMEOUT, relay.check_relay_connection()).await {
+        Ok(Ok(conn_id)) => {
+            return Check::pass(NAME, format!("{endpoint} (connection id {conn_id})"));
+        }
+        Ok(Err(err)) => err,
+        Err(_) => {
+            return Check::fail(
+                NAME,
+                format!("{endpoint} didn't answer within {RELAY_TIMEOUT:?}"),
+                format!("check that {host} is reachable from this host, and that no firewall drops the connection"),
+            );
+        }
+    };
+
+    let hint = match err.downcast_ref::<tungstenite::Error>() {
+        Some(tungstenite::Error::Http(response))
+            if response.status().as_u16() == 401 || response.status().as_u16() == 403 =>
+        {
+            "the token was rejected: check that the daemon still exists in the workspace, or create a new token with `fp daemon create`".to_string()
+        }
+        Some(tungstenite::Error::Http(_)) => {
+            "check that --api-base (or API_BASE) points to Fiberplane".to_string()
+        }
+        Some(_) => format!(
+            "check that {host} is reachable from this host, and that proxies and firewalls allow WebSocket connections"
+        ),
+        None => "check that --api-base (or API_BASE) points to Fiberplane, and that no proxy in between removes the fp-conn-id header".to_string(),
+    };
+    Check::fail(NAME, format!("{endpoint}: {err}"), hint)
+}
@@ -0,0 +1,141 @@
+use super::{diagnose, Check, CheckStatus, Diagnosis, DoctorOptions};
+use std::{fs, path::Path};
+use tempfile::TempDir;
+
+fn options(dir: &Path, token: Option<&str>) -> DoctorOptions {
+    DoctorOptions {
+        wasm_dir: Some(dir.join("providers")),
+        data_sources_path: Some(dir.join("data_sources.yaml")),
+        token: token.map(ToString::to_string),
+        api_base: "ws://127.0.0.1:1".parse().unwrap(),
+        query_limits: Default::default(),
+    }
+}
+
+fn statuses(diagnosis: &Diagnosis) -> Vec<(&str, CheckStatus)> {
+    diagnosis
+        .checks
+        .iter()
+        .map(|check| (check.name.as_str(), check.status))
+        .collect()
+}
+
+#[tokio::test]
+async fn reports_missing_providers_and_token() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("providers")).unwrap();
+    fs::write(
+        dir.join("data_sources.yaml"),
+        "- name: prometheus-demo\n  providerType: prometheus\n  config: {}\n",
+    )
+    .unwrap();
+
+    let diagnosis = diagnose(options(dir, None)).await;
+
+    assert_eq!(
+        statuses(&diagnosis),
+        vec![
+            ("Data sources path", CheckStatus::Pass),
+            ("Wasm directory", CheckStatus::Pass),
+            ("Data sources files", CheckStatus::Pass),
+            ("Data sources YAML", CheckStatus::Pass),
+            ("Provider prometheus", CheckStatus::Fail),
+            ("Token", CheckStatus::Fail),
+            ("Relay connection", CheckStatus::Skip),
+            ("Data source prometheus-demo", CheckStatus::Fail),
+        ]
+    );
+    assert_eq!(diagnosis.failed(), 3);
+
+    let provider = &diagnosis.checks[4];
+    assert!(provider
+        .detail
+        .ends_with("doesn't exist (used by prometheus-demo)"));
+    assert!(provider
+        .hint
+        .as_ref()
+        .unwrap()
+        .starts_with("run `fpd pull prometheus`"));
+    assert_eq!(diagnosis.checks[5].detail, "no token given");
+}
+
+#[tokio::test]
+async fn skips_the_checks_depending_on_failed_ones() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(
+        dir.join("data_sources.yaml"),
+        "- name: prometheus-demo\n  providerType: [prometheus\n",
+    )
+    .unwrap();
+
+    let diagnosis = diagnose(options(dir, Some("not-a-token"))).await;
+
+    assert_eq!(
+        statuses(&diagnosis),
+        vec![
+            ("Data sources path", CheckStatus::Pass),
+            ("Wasm directory", CheckStatus::Fail),
+            ("Data sources files", CheckStatus::Pass),
+            ("Data sources YAML", CheckStatus::Fail),
+            ("Providers", CheckStatus::Skip),
+            ("Token", CheckStatus::Fail),
+            ("Relay connection", CheckStatus::Skip),
+            ("Data sources status", CheckStatus::Skip),
+        ]
+    );
+    assert!(diagnosis.checks[1]
+        .hint
+        .as_ref()
+        .unwrap()
+        .starts_with("run `fpd pull --all`"));
+    assert!(diagnosis.checks[5]
+        .detail
+        .starts_with("unable to parse the token"));
+}
+
+#[test]
+fn formats_the_checklist() {
+    let diagnosis = Diagnosis {
+        checks: vec![
+            Check::pass("Token", "workspace abc, daemon dev"),
+            Check::fail(
+                "Relay connection",
+                "ws://localhost/ws: connection refused",
+                "check that localhost is reachable from this host",
+            ),
+            Check::skip("Data sources status", "no data sources loaded"),
+        ],
+    };
+    assert_eq!(
+        diagnosis.to_string(),
+        "\
+[PASS] Token: workspace abc, daemon dev
+[FAIL] Relay connection: ws://localhost/ws: connection refused
+       hint: check that localhost is reachable from this host
+[SKIP] Data sources status: no data sources loaded
+"
+    );
+}
+
+#[tokio::test]
+async fn refuses_tokens_with_surrounding_whitespace() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("data_sources.yaml"), "[]\n").unwrap();
+
+    let diagnosis = diagnose(options(
+        dir,
+        Some("MVPpfxAYRxcQ4rFZUB7RRzirzwhR7htlkU3zcDm-pZk\n"),
+    ))
+    .await;
+
+    let token = diagnosis
+        .checks
+        .iter()
+        .find(|check| check.name == "Token")
+        .unwrap();
+    assert_eq!(token.status, CheckStatus::Fail);
+    assert_eq!(token.detail, "the token starts or ends with whitespace");
+}
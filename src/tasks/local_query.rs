@@ -0,0 +1,211 @@
+//! Queries run against a configured data source from the terminal, without
+//! going through Studio
+
+use super::service::{bindings, load_wasm_modules};
+use super::table::write_table;
+use crate::data_sources;
+use anyhow::{anyhow, bail, Context, Result};
+use fiberplane::models::providers::Error;
+use fiberplane::provider_bindings::{Blob, Cell, ProviderRequest};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{fmt, path::Path, time::Duration};
+use tracing::debug;
+
+#[cfg(test)]
+mod tests;
+
+/// MIME type of the query data sent by Studio
+pub const FORM_ENCODED_MIME_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// A query to run against a data source
+#[derive(Debug, Clone)]
+pub struct LocalQuery {
+    /// Name of the data source to query
+    pub data_source: String,
+    pub query_type: String,
+    pub query_data: String,
+    /// MIME type of `query_data`
+    pub mime_type: String,
+    /// Turn the result into notebook cells with the provider
+    pub create_cells: bool,
+}
+
+/// Result of a query, as returned by the provider or turned into cells
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum QueryOutput {
+    #[serde(rename_all = "camelCase")]
+    Blob {
+        mime_type: String,
+        /// Data of the result, decoded according to its MIME type
+        data: Value,
+    },
+    Cells(Vec<Cell>),
+}
+
+impl QueryOutput {
+    fn from_blob(blob: Blob) -> Self {
+        let data = decode_data(&blob.mime_type, &blob.data[..]);
+        QueryOutput::Blob {
+            mime_type: blob.mime_type,
+            data,
+        }
+    }
+}
+
+impl fmt::Display for QueryOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryOutput::Blob { mime_type, data } => {
+                writeln!(f, "Result ({mime_type}):")?;
+                write_value(f, data)
+            }
+            QueryOutput::Cells(cells) => {
+                let cells: Vec<Value> = cells
+                    .iter()
+                    .map(|cell| serde_json::to_value(cell).unwrap_or(Value::Null))
+                    .collect();
+                write_value(f, &Value::Array(cells))
+            }
+        }
+    }
+}
+
+/// Write lists of objects as a table with a column per field, strings as they
+/// are and any other value as JSON
+fn write_value(f: &mut fmt::Formatter<'_>, value: &Value) -> fmt::Result {
+    let objects: Option<Vec<&Map<String, Value>>> = match value {
+        Value::Array(values) if !values.is_empty() => values.iter().map(Value::as_object).collect(),
+        _ => None,
+    };
+    match (objects, value) {
+        (Some(objects), _) => {
+            let mut header: Vec<&str> = Vec::new();
+            for object in &objects {
+                for key in object.keys() {
+                    if !header.contains(&key.as_str()) {
+                        header.push(key);
+                    }
+                }
+            }
+            let rows: Vec<Vec<String>> = objects
+                .iter()
+                .map(|object| {
+                    header
+                        .iter()
+                        .map(|key| match object.get(*key) {
+                            None | Some(Value::Null) => "-".to_string(),
+                            Some(Value::String(text)) => text.replace('\n', " "),
+                            Some(value) => value.to_string(),
+                        })
+                        .collect()
+                })
+                .collect();
+            let header: Vec<String> = header.iter().map(|key| key.to_uppercase()).collect();
+            let header: Vec<&str> = header.iter().map(String::as_str).collect();
+            write_table(f, &header, &rows)
+        }
+        (None, Value::String(text)) => writeln!(f, "{text}"),
+        (None, value) => writeln!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(value).map_err(|_| fmt::Error)?
+        ),
+    }
+}
+
+/// Decode the data of a result: MessagePack and JSON are decoded into values,
+/// other data is kept as text, or base64-encoded if it isn't valid UTF-8
+fn decode_data(mime_type: &str, data: &[u8]) -> Value {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let decoded = if essence.ends_with("+msgpack") || essence.ends_with("/msgpack") {
+        rmp_serde::from_slice(data).ok()
+    } else if essence.ends_with("+json") || essence.ends_with("/json") {
+        serde_json::from_slice(data).ok()
+    } else {
+        None
+    };
+    decoded.unwrap_or_else(|| match std::str::from_utf8(data) {
+        Ok(text) => Value::String(text.to_string()),
+        Err(_) => Value::String(base64::encode(data)),
+    })
+}
+
+/// Load the data source named in `query` from `data_sources_path` and its
+/// provider from `wasm_dir`, and run the query with the config of the data
+/// source.
+///
+/// The query fails with a timeout error after `default_timeout`, unless the
+/// data source sets its own timeout.
+pub async fn run(
+    wasm_dir: &Path,
+    data_sources_path: &Path,
+    query: LocalQuery,
+    default_timeout: Option<Duration>,
+) -> Result<QueryOutput> {
+    let files = data_sources::read(data_sources_path).await?;
+    let data_source = data_sources::parse(&files)?
+        .into_iter()
+        .find(|data_source| data_source.name.to_string() == query.data_source)
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown data source '{}', it isn't defined in {}",
+                query.data_source,
+                data_sources_path.display()
+            )
+        })?;
+
+    let module = data_source.provider_module(wasm_dir);
+    let mut wasm_modules = load_wasm_modules(vec![module.clone()]).await;
+    if data_source.protocol_version(wasm_dir, &wasm_modules) == 1 {
+        bail!(
+            "the '{}' provider uses protocol v1, which doesn't support running queries",
+            data_source.provider_type
+        );
+    }
+    let runtime = match wasm_modules.remove(&module) {
+        Some(Ok(module)) => module.runtime,
+        Some(Err(err)) => bail!(
+            "unable to load the '{}' provider: {err}",
+            data_source.provider_type
+        ),
+        None => unreachable!("the provider is loaded"),
+    };
+
+    let request = ProviderRequest::builder()
+        .query_type(query.query_type.clone())
+        .query_data(
+            Blob::builder()
+                .data(query.query_data.into_bytes())
+                .mime_type(query.mime_type)
+                .build(),
+        )
+        .config(Value::Null)
+        .build();
+    let request = rmp_serde::to_vec_named(&request).context("unable to serialize the query")?;
+
+    debug!(data_source = %data_source.name, query_type = %query.query_type, "Calling provider");
+    let invocation = bindings::invoke_provider_v2(&runtime, request, data_source.config.clone());
+    let response = match data_source.query_timeout(default_timeout) {
+        Some(timeout) => tokio::time::timeout(timeout, invocation)
+            .await
+            .map_err(|_| anyhow!("the provider did not respond within {timeout:?}"))?,
+        None => invocation.await,
+    }?;
+    let result: Result<Blob, Error> =
+        rmp_serde::from_slice(&response).context("unable to deserialize the provider response")?;
+    let blob = result.context("the provider returned an error")?;
+
+    if !query.create_cells {
+        return Ok(QueryOutput::from_blob(blob));
+    }
+    let cells = bindings::create_cells(&runtime, &query.query_type, blob)?
+        .context("the provider was unable to create cells")?;
+    Ok(QueryOutput::Cells(cells))
+}
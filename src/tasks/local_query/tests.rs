@@ -0,0 +1,96 @@
+use super::{decode_data, run, LocalQuery, QueryOutput, FORM_ENCODED_MIME_TYPE};
+use serde_json::json;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn decodes_the_data_of_results() {
+    let timeseries = json!([{ "name": "up", "labels": { "job": "node" } }]);
+    assert_eq!(
+        decode_data(
+            "application/vnd.fiberplane.timeseries+msgpack",
+            &rmp_serde::to_vec_named(&timeseries).unwrap()
+        ),
+        timeseries
+    );
+    assert_eq!(
+        decode_data(
+            "application/json; charset=utf-8",
+            timeseries.to_string().as_bytes()
+        ),
+        timeseries
+    );
+    assert_eq!(decode_data("text/plain", b"ok"), json!("ok"));
+    assert_eq!(
+        decode_data("application/json", b"not json"),
+        json!("not json")
+    );
+    assert_eq!(
+        decode_data("application/octet-stream", &[0xff, 0xfe]),
+        json!("//4=")
+    );
+}
+
+#[test]
+fn formats_results_as_a_table() {
+    let output = QueryOutput::Blob {
+        mime_type: "application/json".to_string(),
+        data: json!([
+            { "labels": { "job": "node" }, "name": "up", "value": 1 },
+            { "help": "Build\ninformation", "name": "build_info", "value": null },
+        ]),
+    };
+    assert_eq!(
+        output.to_string(),
+        "\
+Result (application/json):
+LABELS          NAME        VALUE  HELP
+{\"job\":\"node\"}  up          1      -
+-               build_info  -      Build information
+"
+    );
+
+    let output = QueryOutput::Blob {
+        mime_type: "text/plain".to_string(),
+        data: json!("ok"),
+    };
+    assert_eq!(output.to_string(), "Result (text/plain):\nok\n");
+
+    let output = QueryOutput::Blob {
+        mime_type: "application/json".to_string(),
+        data: json!({ "status": "success" }),
+    };
+    assert_eq!(
+        output.to_string(),
+        "Result (application/json):\n{\n  \"status\": \"success\"\n}\n"
+    );
+}
+
+#[tokio::test]
+async fn refuses_unknown_data_sources() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    let data_sources_path = dir.join("data_sources.yaml");
+    fs::write(
+        &data_sources_path,
+        "- name: prometheus-demo\n  providerType: prometheus\n  config: {}\n",
+    )
+    .unwrap();
+
+    let query = LocalQuery {
+        data_source: "prometheus-prod".to_string(),
+        query_type: "x-timeseries".to_string(),
+        query_data: "q=up".to_string(),
+        mime_type: FORM_ENCODED_MIME_TYPE.to_string(),
+        create_cells: false,
+    };
+    let err = run(dir, &data_sources_path, query, None).await.unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "unknown data source 'prometheus-prod', it isn't defined in {}",
+            data_sources_path.display()
+        )
+    );
+}
@@ -5,6 +5,7 @@ use prometheus::{
 };
 
 static LABELS: [&str; 3] = ["protocol_version", "provider_type", "data_source_name"];
+static BLOCKING_CALL_LABELS: [&str; 3] = ["function", "provider_type", "data_source_name"];
 
 pub static QUERIES_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!("proxy_queries_total", "Number of queries executed", &LABELS).unwrap()
@@ -28,6 +29,69 @@ pub static CONCURRENT_QUERIES: Lazy<IntGaugeVec> = Lazy::new(|| {
     .unwrap()
 });
 
+pub static QUEUED_QUERIES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "proxy_queued_queries",
+        "Number of queries waiting for a free slot because of the concurrency limits",
+        &LABELS
+    )
+    .unwrap()
+});
+
+pub static QUERIES_WAIT_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "proxy_queries_wait_seconds",
+        "Time queries spent waiting for a free slot in seconds",
+        &LABELS
+    )
+    .unwrap()
+});
+
+pub static QUERIES_REJECTED_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_queries_rejected_total",
+        "Number of queries rejected because too many queries were waiting for a free slot",
+        &LABELS
+    )
+    .unwrap()
+});
+
+pub static QUERIES_TIMEOUTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "proxy_queries_timeouts_total",
+        "Number of queries cancelled because the provider did not respond in time",
+        &LABELS
+    )
+    .unwrap()
+});
+
+pub static BLOCKING_CALLS_WAITING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "proxy_blocking_calls_waiting",
+        "Number of synchronous provider calls waiting for a free thread",
+        &BLOCKING_CALL_LABELS
+    )
+    .unwrap()
+});
+
+pub static BLOCKING_CALLS_RUNNING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "proxy_blocking_calls_running",
+        "Number of synchronous provider calls running",
+        &BLOCKING_CALL_LABELS
+    )
+    .unwrap()
+});
+
+pub static BLOCKING_CALLS_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "proxy_blocking_calls_duration_seconds",
+        "Duration of synchronous provider calls in seconds",
+        &BLOCKING_CALL_LABELS
+    )
+    .unwrap()
+});
+
 pub fn metrics_export() -> Result<String, Error> {
     let encoder = TextEncoder::new();
     let metrics = prometheus::gather();
@@ -0,0 +1,186 @@
+//! Description of what a provider expects: the config of its data sources and
+//! the queries it supports
+
+use super::service::{bindings, load_wasm_modules};
+use super::table::write_table;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Attributes of the schema fields that have their own column
+const FIELD_COLUMNS: &[&str] = &["name", "type", "required", "label"];
+
+/// The config schema and supported query types of a provider, in the JSON
+/// representation of the provider protocol
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderDescription {
+    pub path: PathBuf,
+    pub protocol_version: Option<u8>,
+    /// `None` for providers that don't export a config schema
+    pub config_schema: Option<Vec<Value>>,
+    pub supported_query_types: Vec<Value>,
+}
+
+impl fmt::Display for ProviderDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Provider {}", self.path.display())?;
+        if let Some(version) = self.protocol_version {
+            write!(f, " (protocol v{version})")?;
+        }
+        writeln!(f)?;
+
+        match &self.config_schema {
+            Some(config_schema) => {
+                writeln!(f, "\nConfig schema:")?;
+                write_fields(f, config_schema)?;
+            }
+            None => writeln!(f, "\nNo config schema exported")?,
+        }
+
+        for query_type in &self.supported_query_types {
+            let name = query_type
+                .get("queryType")
+                .and_then(Value::as_str)
+                .unwrap_or("-");
+            let mime_types: Vec<&str> = query_type
+                .get("mimeTypes")
+                .and_then(Value::as_array)
+                .map(|mime_types| mime_types.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            writeln!(f, "\nQuery type {name}:")?;
+            if let Some(label) = query_type.get("label").and_then(Value::as_str) {
+                writeln!(f, "Label: {label}")?;
+            }
+            if !mime_types.is_empty() {
+                writeln!(f, "MIME types: {}", mime_types.join(", "))?;
+            }
+            let schema = query_type
+                .get("schema")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            write_fields(f, schema)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the fields of a config or query schema as a table, with the
+/// attributes specific to each type of field in the last column
+fn write_fields(f: &mut fmt::Formatter<'_>, fields: &[Value]) -> fmt::Result {
+    if fields.is_empty() {
+        return writeln!(f, "No fields");
+    }
+
+    let header = ["NAME", "TYPE", "REQUIRED", "LABEL", "DETAILS"];
+    let rows: Vec<[String; 5]> = fields
+        .iter()
+        .map(|field| {
+            let text = |attribute: &str| {
+                field
+                    .get(attribute)
+                    .and_then(Value::as_str)
+                    .filter(|text| !text.is_empty())
+                    .unwrap_or("-")
+                    .to_string()
+            };
+            let required = field
+                .get("required")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let details: Vec<String> = field
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(attribute, value)| {
+                    !FIELD_COLUMNS.contains(&attribute.as_str())
+                        && !matches!(value, Value::Null | Value::Bool(false))
+                        && value.as_str() != Some("")
+                })
+                .map(|(attribute, value)| match value {
+                    Value::Bool(true) => attribute.clone(),
+                    Value::String(text) => format!("{attribute}={text}"),
+                    value => format!("{attribute}={value}"),
+                })
+                .collect();
+            [
+                text("name"),
+                text("type"),
+                match required {
+                    true => "yes".to_string(),
+                    false => "no".to_string(),
+                },
+                text("label"),
+                if details.is_empty() {
+                    "-".to_string()
+                } else {
+                    details.join(", ")
+                },
+            ]
+        })
+        .collect();
+    write_table(f, &header, &rows)
+}
+
+/// Path of the module of a provider given as a path, or as a provider name
+/// (optionally followed by `@<version>`) installed in `wasm_dir`
+pub fn provider_module(wasm_dir: &Path, provider: &str) -> PathBuf {
+    if provider.ends_with(".wasm") || provider.contains(std::path::is_separator) {
+        PathBuf::from(provider)
+    } else {
+        wasm_dir.join(format!("{provider}.wasm"))
+    }
+}
+
+/// Load the provider module at `path`, and ask it for its config schema (if it
+/// exports one) and for the query types it supports with the given data source
+/// `config`
+pub async fn inspect(path: &Path, config: Map<String, Value>) -> Result<ProviderDescription> {
+    let module = match load_wasm_modules(vec![path.to_path_buf()])
+        .await
+        .remove(path)
+    {
+        Some(Ok(module)) => module,
+        Some(Err(err)) => bail!("unable to load the provider {}: {err}", path.display()),
+        None => unreachable!("the provider is loaded"),
+    };
+    if module.protocol_version == Some(1) {
+        bail!(
+            "the provider {} uses protocol v1, which has no config schema or query types",
+            path.display()
+        );
+    }
+
+    let config_schema = if module.has_config_schema {
+        let config_schema = bindings::get_config_schema(&module.runtime)
+            .context("unable to get the config schema of the provider")?;
+        Some(to_values(&config_schema)?)
+    } else {
+        None
+    };
+    let supported_query_types = bindings::get_supported_query_types(&module.runtime, &config)
+        .await
+        .context("unable to get the supported query types of the provider")?;
+
+    Ok(ProviderDescription {
+        path: path.to_path_buf(),
+        protocol_version: module.protocol_version,
+        config_schema,
+        supported_query_types: to_values(&supported_query_types)?,
+    })
+}
+
+fn to_values<T: Serialize>(values: &[T]) -> Result<Vec<Value>> {
+    values
+        .iter()
+        .map(|value| serde_json::to_value(value).map_err(Into::into))
+        .collect()
+}
@@ -0,0 +1,128 @@
+use super::{inspect, provider_module, ProviderDescription};
+use serde_json::{json, Map};
+use std::{env, fs, path::Path, path::PathBuf};
+use tempfile::TempDir;
+
+#[test]
+fn formats_the_description_of_providers() {
+    let description = ProviderDescription {
+        path: PathBuf::from("/providers/prometheus.wasm"),
+        protocol_version: Some(2),
+        config_schema: Some(vec![
+            json!({
+                "type": "text",
+                "name": "url",
+                "label": "Prometheus URL",
+                "placeholder": "http://localhost:9090",
+                "required": true,
+                "multiline": false,
+            }),
+            json!({ "type": "checkbox", "name": "verbose", "label": "", "checked": true }),
+        ]),
+        supported_query_types: vec![
+            json!({
+                "queryType": "x-timeseries",
+                "label": "Timeseries query",
+                "mimeTypes": ["application/vnd.fiberplane.timeseries+msgpack"],
+                "schema": [
+                    { "type": "text", "name": "query", "label": "Query", "required": true },
+                ],
+            }),
+            json!({ "queryType": "status", "mimeTypes": ["text/plain"], "schema": [] }),
+        ],
+    };
+
+    assert_eq!(
+        description.to_string(),
+        "\
+Provider /providers/prometheus.wasm (protocol v2)
+
+Config schema:
+NAME     TYPE      REQUIRED  LABEL           DETAILS
+url      text      yes       Prometheus URL  placeholder=http://localhost:9090
+verbose  checkbox  no        -               checked
+
+Query type x-timeseries:
+Label: Timeseries query
+MIME types: application/vnd.fiberplane.timeseries+msgpack
+NAME   TYPE  REQUIRED  LABEL  DETAILS
+query  text  yes       Query  -
+
+Query type status:
+MIME types: text/plain
+No fields
+"
+    );
+}
+
+#[test]
+fn finds_the_module_of_providers() {
+    let wasm_dir = PathBuf::from("/providers");
+    assert_eq!(
+        provider_module(&wasm_dir, "prometheus"),
+        PathBuf::from("/providers/prometheus.wasm")
+    );
+    assert_eq!(
+        provider_module(&wasm_dir, "prometheus@v2.4.0"),
+        PathBuf::from("/providers/prometheus@v2.4.0.wasm")
+    );
+    assert_eq!(
+        provider_module(&wasm_dir, "target/prometheus.wasm"),
+        PathBuf::from("target/prometheus.wasm")
+    );
+}
+
+#[tokio::test]
+async fn refuses_modules_that_dont_load() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("broken.wasm");
+    fs::write(&path, "not a wasm module").unwrap();
+
+    let err = inspect(&path, Map::new()).await.unwrap_err();
+
+    assert!(err
+        .to_string()
+        .starts_with(&format!("unable to load the provider {}", path.display())));
+}
+
+#[tokio::test]
+async fn lists_the_query_types_of_providers_without_a_config_schema() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("providers/prometheus.wasm");
+
+    let description = inspect(&path, Map::new()).await.unwrap();
+
+    assert_eq!(description.protocol_version, Some(2));
+    assert_eq!(description.config_schema, None);
+    assert!(!description.supported_query_types.is_empty());
+    let output = description.to_string();
+    assert!(output.contains("\nNo config schema exported\n"));
+    for query_type in &description.supported_query_types {
+        let name = query_type["queryType"].as_str().unwrap();
+        assert!(output.contains(&format!("\nQuery type {name}:\n")));
+    }
+}
+
+#[test]
+fn formats_the_description_of_providers_without_a_config_schema() {
+    let description = ProviderDescription {
+        path: PathBuf::from("/providers/prometheus.wasm"),
+        protocol_version: Some(2),
+        config_schema: None,
+        supported_query_types: vec![
+            json!({ "queryType": "status", "mimeTypes": ["text/plain"], "schema": [] }),
+        ],
+    };
+
+    assert_eq!(
+        description.to_string(),
+        "\
+Provider /providers/prometheus.wasm (protocol v2)
+
+No config schema exported
+
+Query type status:
+MIME types: text/plain
+No fields
+"
+    );
+}
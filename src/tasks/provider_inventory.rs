@@ -0,0 +1,216 @@
+//! Inventory of the providers installed in the wasm directory
+
+use super::provider_manager::{sha256_hex, InstalledProviders};
+use super::service::{bindings, detect_protocol_version};
+use super::table::write_table;
+use crate::data_sources;
+use anyhow::Result;
+use fiberplane::provider_runtime::spec::Runtime;
+use serde::Serialize;
+use serde_json::Map;
+use std::{fmt, path::Path};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::fs;
+
+#[cfg(test)]
+mod tests;
+
+/// Number of characters of the checksums shown in the table
+const SHORT_CHECKSUM_LEN: usize = 12;
+
+/// A provider found in the wasm directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderInfo {
+    /// Name of the provider, which is the provider type of the data sources
+    /// using it, followed by `@<version>` for a version installed side by side
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Last modification time, in RFC 3339 format
+    pub modified: Option<String>,
+    /// Protocol version detected from the exports of the module
+    pub protocol_version: Option<u8>,
+    /// Release the provider was pulled from, if it was installed by `fpd pull`
+    pub pulled_version: Option<String>,
+    /// Whether the module loads and compiles
+    pub loads: bool,
+    pub supported_query_types: Vec<String>,
+    /// Problem found while loading the provider or asking for its supported
+    /// query types
+    pub error: Option<String>,
+    /// Names of the data sources using the provider
+    pub data_sources: Vec<String>,
+}
+
+/// All the providers of the wasm directory, sorted by name
+#[derive(Debug, Serialize)]
+pub struct Inventory {
+    pub providers: Vec<ProviderInfo>,
+}
+
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.providers.is_empty() {
+            return writeln!(f, "No providers installed");
+        }
+
+        let header = [
+            "NAME",
+            "PROTOCOL",
+            "VERSION",
+            "SIZE",
+            "SHA256",
+            "MODIFIED",
+            "STATUS",
+            "QUERY TYPES",
+            "DATA SOURCES",
+        ];
+        let rows: Vec<[String; 9]> = self
+            .providers
+            .iter()
+            .map(|provider| {
+                [
+                    provider.name.clone(),
+                    provider
+                        .protocol_version
+                        .map_or_else(|| "-".to_string(), |version| format!("v{version}")),
+                    provider
+                        .pulled_version
+                        .clone()
+                        .unwrap_or_else(|| "-".to_string()),
+                    provider.size.to_string(),
+                    provider.sha256[..SHORT_CHECKSUM_LEN].to_string(),
+                    provider.modified.clone().unwrap_or_else(|| "-".to_string()),
+                    match (provider.loads, &provider.error) {
+                        (false, _) => "error".to_string(),
+                        (true, Some(_)) => "warning".to_string(),
+                        (true, None) => "ok".to_string(),
+                    },
+                    join_or_dash(&provider.supported_query_types),
+                    join_or_dash(&provider.data_sources),
+                ]
+            })
+            .collect();
+
+        write_table(f, &header, &rows)?;
+
+        for provider in &self.providers {
+            if let Some(error) = &provider.error {
+                writeln!(f, "\n{}: {error}", provider.name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join_or_dash(values: &[String]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values.join(",")
+    }
+}
+
+/// Scan `wasm_dir` for providers, load each of them, and find the data sources
+/// of `data_sources_path` using them.
+///
+/// A missing data sources file is treated as an empty one.
+pub async fn list(wasm_dir: &Path, data_sources_path: &Path) -> Result<Inventory> {
+    let data_sources = match data_sources::read(data_sources_path).await {
+        Ok(files) => data_sources::parse(&files)?,
+        Err(data_sources::Error::NotFound { .. }) => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let installed = InstalledProviders::read(wasm_dir)?;
+
+    let mut paths = Vec::new();
+    let mut entries = fs::read_dir(wasm_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "wasm")
+            && path.is_file()
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut providers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            // Skip hidden files
+            Some(name) if !name.starts_with('.') => name.to_string(),
+            _ => continue,
+        };
+        let contents = fs::read(&path).await?;
+        let metadata = fs::metadata(&path).await?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| OffsetDateTime::from(modified).format(&Rfc3339).ok());
+
+        let users: Vec<_> = data_sources
+            .iter()
+            .filter(|data_source| data_source.provider_module(wasm_dir) == path)
+            .collect();
+        let protocol_version = detect_protocol_version(&contents);
+
+        let (loads, supported_query_types, error) = match Runtime::new(&contents) {
+            Ok(runtime) if protocol_version == Some(2) => {
+                // Ask without a config: the config of a data source could make
+                // the provider call it, and the list is only an overview (see
+                // `fpd providers inspect` for the query types of a config)
+                match bindings::get_supported_query_types(&runtime, &Map::new()).await {
+                    Ok(query_types) => (
+                        true,
+                        query_types
+                            .into_iter()
+                            .map(|query_type| query_type.query_type)
+                            .collect(),
+                        None,
+                    ),
+                    Err(err) => (
+                        true,
+                        Vec::new(),
+                        Some(format!("unable to get the supported query types: {err}")),
+                    ),
+                }
+            }
+            Ok(_) if protocol_version.is_none() => (
+                true,
+                Vec::new(),
+                Some("unable to detect the protocol version of the module".to_string()),
+            ),
+            Ok(_) => (true, Vec::new(), None),
+            Err(err) => (
+                false,
+                Vec::new(),
+                Some(format!("unable to compile the module: {err}")),
+            ),
+        };
+
+        providers.push(ProviderInfo {
+            size: metadata.len(),
+            sha256: sha256_hex(&contents),
+            modified,
+            protocol_version,
+            pulled_version: installed
+                .providers
+                .get(&name)
+                .map(|provider| provider.version.clone()),
+            loads,
+            supported_query_types,
+            error,
+            data_sources: users
+                .iter()
+                .map(|data_source| data_source.name.to_string())
+                .collect(),
+            name,
+        });
+    }
+
+    Ok(Inventory { providers })
+}
@@ -0,0 +1,87 @@
+use super::{list, Inventory, ProviderInfo};
+use std::fs;
+use tempfile::TempDir;
+
+fn provider(name: &str) -> ProviderInfo {
+    ProviderInfo {
+        name: name.to_string(),
+        size: 1024,
+        sha256: "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08".to_string(),
+        modified: Some("2023-02-01T10:00:00Z".to_string()),
+        protocol_version: Some(2),
+        pulled_version: None,
+        loads: true,
+        supported_query_types: Vec::new(),
+        error: None,
+        data_sources: Vec::new(),
+    }
+}
+
+#[test]
+fn formats_the_inventory_as_a_table() {
+    let inventory = Inventory {
+        providers: vec![
+            ProviderInfo {
+                pulled_version: Some("v2.3.0".to_string()),
+                supported_query_types: vec!["timeseries".to_string(), "x-instants".to_string()],
+                data_sources: vec!["prometheus-prod".to_string()],
+                ..provider("prometheus")
+            },
+            ProviderInfo {
+                loads: false,
+                error: Some("unable to compile the module: invalid magic".to_string()),
+                ..provider("sentry")
+            },
+        ],
+    };
+
+    assert_eq!(
+        inventory.to_string(),
+        "\
+NAME        PROTOCOL  VERSION  SIZE  SHA256        MODIFIED              STATUS  QUERY TYPES            DATA SOURCES
+prometheus  v2        v2.3.0   1024  9f86d081884c  2023-02-01T10:00:00Z  ok      timeseries,x-instants  prometheus-prod
+sentry      v2        -        1024  9f86d081884c  2023-02-01T10:00:00Z  error   -                      -
+
+sentry: unable to compile the module: invalid magic
+"
+    );
+    assert_eq!(
+        Inventory {
+            providers: Vec::new()
+        }
+        .to_string(),
+        "No providers installed\n"
+    );
+}
+
+#[tokio::test]
+async fn lists_the_providers_of_the_wasm_dir() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("broken.wasm"), "not a wasm module").unwrap();
+    fs::write(dir.join(".broken.wasm.download"), "partial").unwrap();
+    fs::write(dir.join("README.md"), "not a provider").unwrap();
+    let data_sources_path = dir.join("data_sources.yaml");
+    fs::write(
+        &data_sources_path,
+        "- name: broken-one\n  providerType: broken\n  config: {}\n\
+         - name: other\n  providerType: prometheus\n  config: {}\n\
+         - name: broken-two\n  providerType: broken\n  config: {}\n",
+    )
+    .unwrap();
+
+    let inventory = list(dir, &data_sources_path).await.unwrap();
+
+    assert_eq!(inventory.providers.len(), 1);
+    let provider = &inventory.providers[0];
+    assert_eq!(provider.name, "broken");
+    assert_eq!(provider.size, 17);
+    assert_eq!(
+        provider.sha256,
+        "09a2eca54bb80bc38e0ac2ac2332b3101676f6ab4ecfc64b152c87e396241e6a"
+    );
+    assert_eq!(provider.protocol_version, None);
+    assert!(!provider.loads);
+    assert!(provider.error.is_some());
+    assert_eq!(provider.data_sources, vec!["broken-one", "broken-two"]);
+}
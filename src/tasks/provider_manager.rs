@@ -1,9 +1,28 @@
 //! Tasks to handle provider management
 
-use crate::{cli::BuiltinProvider, runtime::providers_wasm_dir};
-use std::path::PathBuf;
+use crate::cli::{BuiltinProvider, ProviderSpec};
+use anyhow::anyhow;
+use reqwest::StatusCode;
+use ring::{
+    digest::{digest, SHA256},
+    signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use thiserror::Error;
-use tracing::info;
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::{info, warn};
+use url::Url;
+
+mod bundle;
+#[cfg(test)]
+mod tests;
+
+pub use bundle::{bundle, install_bundle};
 
 pub const ALL_PROVIDERS: &[BuiltinProvider] = &[
     BuiltinProvider::Sentry,
@@ -13,6 +32,18 @@ pub const ALL_PROVIDERS: &[BuiltinProvider] = &[
     BuiltinProvider::Prometheus,
 ];
 
+/// Repository the providers are pulled from by default
+pub const REPOSITORY_URL: &str = "https://github.com/fiberplane/proxy";
+/// Git reference to pull the providers from when no version is given
+const LATEST_VERSION: &str = "main";
+/// File recording the providers installed by `fpd pull`, in the wasm directory
+const INSTALLED_PROVIDERS_FILE: &str = "installed.json";
+/// Manifest with the SHA-256 checksums of the providers of a release, in the
+/// format of `sha256sum`
+const CHECKSUMS_FILE: &str = "SHA256SUMS";
+/// Detached ed25519 signature of the checksums manifest, encoded in base64
+const CHECKSUMS_SIGNATURE_FILE: &str = "SHA256SUMS.sig";
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Runtime error: {0}")]
@@ -21,10 +52,39 @@ pub enum Error {
     Network(#[from] reqwest::Error),
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
-    #[error("Provider '{provider}' not found")]
-    NotFound { provider: String },
-    #[error("Provider '{provider}' already exists at '{}', delete it manually if you want to replace it", path.display())]
+    #[error("Provider '{provider}' not found (version '{version}')")]
+    NotFound { provider: String, version: String },
+    #[error("Provider '{provider}' already exists at '{}', use --update or --force to replace it", path.display())]
     NoOverwrite { provider: String, path: PathBuf },
+    #[error("Invalid public key: {0}")]
+    InvalidPublicKey(String),
+    #[error("Invalid signing key: {0}")]
+    InvalidSigningKey(String),
+    #[error("Checksums manifest not found at {location}")]
+    ManifestNotFound { location: String },
+    #[error("Checksums manifest not found at {location}, use --allow-unverified to install the providers of version '{version}' without verifying them")]
+    Unverified { location: String, version: String },
+    #[error("Invalid checksums manifest at {location}: {message}")]
+    InvalidManifest { location: String, message: String },
+    #[error("Signature of the checksums manifest at {location} does not match the public key")]
+    InvalidSignature { location: String },
+    #[error(
+        "Provider '{provider}' is not listed in the checksums manifest of version '{version}'"
+    )]
+    ChecksumMissing { provider: String, version: String },
+    #[error("Checksum mismatch for provider '{provider}': expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        provider: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Invalid bundle '{}': {message}", path.display())]
+    InvalidBundle { path: PathBuf, message: String },
+    #[error("Invalid record of the installed providers at '{}': {error}", path.display())]
+    InstalledRecord {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
     #[error(
 
                         "Errors while fetching providers:\n\t{}",
@@ -38,11 +98,385 @@ pub enum Error {
     Multiple { errors: Vec<Error> },
 }
 
-pub async fn pull(providers: &[BuiltinProvider], all: bool) -> Result<(), Error> {
-    let wasm_dir = providers_wasm_dir()?;
+/// What to do with the providers that are already installed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallMode {
+    /// Only install missing providers, and fail for the others
+    Missing,
+    /// Replace the providers that were installed from another version, or
+    /// from the latest version (which may have changed since)
+    Update,
+    /// Always replace the providers
+    Force,
+}
+
+/// Record of the providers installed by `fpd pull`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledProviders {
+    pub providers: BTreeMap<String, InstalledProvider>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstalledProvider {
+    /// Release the provider was pulled from (`main` for the latest version)
+    pub version: String,
+    /// Where the provider was downloaded from
+    pub url: String,
+}
+
+impl InstalledProviders {
+    /// Read the record of installed providers of the wasm directory, which is
+    /// empty if no provider was pulled yet
+    pub fn read(wasm_dir: &Path) -> Result<Self, Error> {
+        let path = wasm_dir.join(INSTALLED_PROVIDERS_FILE);
+        match std::fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|error| Error::InstalledRecord { path, error }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write(&self, wasm_dir: &Path) -> Result<(), Error> {
+        let contents = serde_json::to_vec_pretty(self).map_err(|error| Error::InstalledRecord {
+            path: wasm_dir.join(INSTALLED_PROVIDERS_FILE),
+            error,
+        })?;
+        write_atomically(wasm_dir, INSTALLED_PROVIDERS_FILE, &contents).await?;
+        Ok(())
+    }
+}
+
+/// Checks the downloaded providers against the checksums manifest of their
+/// release, and the manifest against its signature if a public key is given.
+///
+/// Releases that don't publish a manifest are refused, unless unverified
+/// providers are explicitly allowed: they are then installed unchecked, with a
+/// warning.
+pub(crate) struct Verifier {
+    registry: Registry,
+    public_key: Option<Vec<u8>>,
+    allow_unverified: bool,
+    /// Checksums by file name, for each version (`None` if the release has no
+    /// manifest)
+    manifests: HashMap<String, Option<HashMap<String, String>>>,
+}
+
+impl Verifier {
+    pub(crate) fn new(
+        registry: &Registry,
+        public_key: Option<&str>,
+        allow_unverified: bool,
+    ) -> Result<Self, Error> {
+        let public_key = public_key.map(parse_public_key).transpose()?;
+        Ok(Self {
+            registry: registry.clone(),
+            public_key,
+            allow_unverified,
+            manifests: HashMap::new(),
+        })
+    }
+
+    /// Check that `contents` match the checksum of `file_name` in the manifest
+    /// of `version`.
+    async fn verify(
+        &mut self,
+        provider: &str,
+        version: &str,
+        file_name: &str,
+        contents: &[u8],
+    ) -> Result<(), Error> {
+        if !self.manifests.contains_key(version) {
+            let manifest = match self.fetch_manifest(version).await {
+                Ok(manifest) => Some(manifest),
+                Err(Error::ManifestNotFound { location }) if self.public_key.is_none() => {
+                    if !self.allow_unverified {
+                        return Err(Error::Unverified {
+                            location,
+                            version: version.to_string(),
+                        });
+                    }
+                    warn!("No checksums manifest found at {location}, the providers of version '{version}' are installed without being verified");
+                    None
+                }
+                Err(err) => return Err(err),
+            };
+            self.manifests.insert(version.to_string(), manifest);
+        }
+
+        let manifest = match &self.manifests[version] {
+            Some(manifest) => manifest,
+            None => return Ok(()),
+        };
+        let expected = manifest
+            .get(file_name)
+            .ok_or_else(|| Error::ChecksumMissing {
+                provider: provider.to_string(),
+                version: version.to_string(),
+            })?;
+        let actual = sha256_hex(contents);
+        if *expected != actual {
+            return Err(Error::ChecksumMismatch {
+                provider: provider.to_string(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    async fn fetch_manifest(&self, version: &str) -> Result<HashMap<String, String>, Error> {
+        let location = self.registry.location(version, CHECKSUMS_FILE);
+        let manifest = self
+            .registry
+            .fetch(version, CHECKSUMS_FILE)
+            .await?
+            .ok_or_else(|| Error::ManifestNotFound {
+                location: location.clone(),
+            })?;
+
+        if let Some(public_key) = &self.public_key {
+            let signature_location = self.registry.location(version, CHECKSUMS_SIGNATURE_FILE);
+            let signature = self
+                .registry
+                .fetch(version, CHECKSUMS_SIGNATURE_FILE)
+                .await?
+                .ok_or_else(|| Error::ManifestNotFound {
+                    location: signature_location.clone(),
+                })?;
+            let signature = decode_signature(&signature).ok_or_else(|| Error::InvalidManifest {
+                location: signature_location,
+                message: "the signature is not valid base64".to_string(),
+            })?;
+            UnparsedPublicKey::new(&ED25519, public_key)
+                .verify(&manifest, &signature)
+                .map_err(|_| Error::InvalidSignature {
+                    location: location.clone(),
+                })?;
+        }
+
+        parse_manifest(&manifest).map_err(|message| Error::InvalidManifest { location, message })
+    }
+}
+
+/// Decode a base64-encoded ed25519 public key
+fn parse_public_key(key: &str) -> Result<Vec<u8>, Error> {
+    let key = base64::decode(key.trim()).map_err(|err| Error::InvalidPublicKey(err.to_string()))?;
+    if key.len() != 32 {
+        return Err(Error::InvalidPublicKey(format!(
+            "expected an ed25519 key of 32 bytes, found {} bytes",
+            key.len()
+        )));
+    }
+    Ok(key)
+}
+
+/// Decode a base64-encoded ed25519 private key (its 32 bytes seed), used to
+/// sign the manifest of bundles
+pub fn parse_signing_key(key: &str) -> Result<Ed25519KeyPair, Error> {
+    let seed =
+        base64::decode(key.trim()).map_err(|err| Error::InvalidSigningKey(err.to_string()))?;
+    Ed25519KeyPair::from_seed_unchecked(&seed).map_err(|_| {
+        Error::InvalidSigningKey(format!(
+            "expected an ed25519 seed of 32 bytes, found {} bytes",
+            seed.len()
+        ))
+    })
+}
+
+/// Decode a base64-encoded detached signature
+fn decode_signature(signature: &[u8]) -> Option<Vec<u8>> {
+    std::str::from_utf8(signature)
+        .ok()
+        .and_then(|signature| base64::decode(signature.trim()).ok())
+}
+
+/// Parse the lines of a `sha256sum` output into checksums by file name
+fn parse_manifest(manifest: &[u8]) -> Result<HashMap<String, String>, String> {
+    let manifest = std::str::from_utf8(manifest).map_err(|err| err.to_string())?;
+    manifest
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let (checksum, file_name) = line
+                .split_once(char::is_whitespace)
+                .filter(|(checksum, _)| {
+                    checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())
+                })
+                .ok_or_else(|| format!("line {} is not a SHA-256 checksum", index + 1))?;
+            // `sha256sum` marks files read in binary mode with a `*`
+            let file_name = file_name.trim().trim_start_matches('*');
+            Ok((file_name.to_string(), checksum.to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// Whether `name` can be used as a provider name, which is also the file name
+/// of the provider in the wasm directory
+pub fn is_valid_provider_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Name of the module of a provider in the wasm directory, without its
+/// extension: the name of the provider, followed by `@<version>` when it's
+/// pinned to a release
+pub fn module_name(provider: &str, version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!("{provider}@{version}"),
+        None => provider.to_string(),
+    }
+}
+
+/// Whether `name` can be the name of a provider module (see [module_name])
+pub fn is_valid_module_name(name: &str) -> bool {
+    match name.split_once('@') {
+        Some((provider, version)) => {
+            is_valid_provider_name(provider) && is_valid_provider_version(version)
+        }
+        None => is_valid_provider_name(name),
+    }
+}
+
+/// Whether `version` can be used as a provider version, which is part of the
+/// file name of the provider in the wasm directory and of its URL in the
+/// registry
+pub fn is_valid_provider_version(version: &str) -> bool {
+    !version.is_empty() && !version.contains(['/', '\\']) && !matches!(version, "." | "..")
+}
+
+/// SHA-256 digest of `contents`, as lowercase hex
+pub(crate) fn sha256_hex(contents: &[u8]) -> String {
+    digest(&SHA256, contents)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Where the providers are pulled from.
+///
+/// Both kinds of registries use the layout of the repository: the files of a
+/// release are in `raw/<version>/providers/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Registry {
+    /// Base URL of the repository, or of a mirror
+    Http(String),
+    /// Local directory, like a mirror copied to an air-gapped network
+    Local(PathBuf),
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::Http(REPOSITORY_URL.to_string())
+    }
+}
+
+impl FromStr for Registry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Registry::Http(s.trim_end_matches('/').to_string()))
+        } else if s.starts_with("file://") {
+            let path = Url::parse(s)?
+                .to_file_path()
+                .map_err(|_| anyhow!("'{s}' is not a valid file URL"))?;
+            Ok(Registry::Local(path))
+        } else if s.is_empty() {
+            Err(anyhow!("the registry can't be empty"))
+        } else {
+            Ok(Registry::Local(PathBuf::from(s)))
+        }
+    }
+}
+
+impl Registry {
+    /// Location of `file_name` of `version`, for messages and records
+    pub fn location(&self, version: &str, file_name: &str) -> String {
+        match self {
+            Registry::Http(base_url) => {
+                format!("{base_url}/raw/{version}/providers/{file_name}")
+            }
+            Registry::Local(dir) => local_path(dir, version, file_name).display().to_string(),
+        }
+    }
+
+    /// Get the contents of `file_name` of `version`, or `None` if it doesn't
+    /// exist
+    async fn fetch(&self, version: &str, file_name: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Registry::Http(_) => {
+                let response = reqwest::get(self.location(version, file_name)).await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let bytes = response.error_for_status()?.bytes().await?;
+                Ok(Some(bytes.to_vec()))
+            }
+            Registry::Local(dir) => match fs::read(local_path(dir, version, file_name)).await {
+                Ok(contents) => Ok(Some(contents)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            },
+        }
+    }
+}
+
+fn local_path(dir: &Path, version: &str, file_name: &str) -> PathBuf {
+    dir.join("raw")
+        .join(version)
+        .join("providers")
+        .join(file_name)
+}
+
+/// Write `contents` to `file_name` in `dir`, so that a crash never leaves a
+/// partially written file: the contents are written to a temporary file that is
+/// synced to disk, and then renamed over the target.
+async fn write_atomically(dir: &Path, file_name: &str, contents: &[u8]) -> std::io::Result<()> {
+    let temp_path = dir.join(format!(".{file_name}.{}.tmp", std::process::id()));
+    let result = async {
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        fs::rename(&temp_path, dir.join(file_name)).await
+    }
+    .await;
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    fs::File::open(dir).await?.sync_all().await?;
+    Ok(())
+}
+
+/// Pull the providers into `wasm_dir`, which is created if needed.
+///
+/// Providers of releases without a checksums manifest are only installed with
+/// `allow_unverified`.
+///
+/// Providers pinned to a release are installed as `<name>@<version>.wasm`,
+/// next to the `<name>.wasm` of the latest version.
+pub async fn pull(
+    wasm_dir: &Path,
+    registry: &Registry,
+    providers: &[ProviderSpec],
+    all: bool,
+    mode: InstallMode,
+    public_key: Option<&str>,
+    allow_unverified: bool,
+) -> Result<(), Error> {
+    let mut verifier = Verifier::new(registry, public_key, allow_unverified)?;
     if wasm_dir.exists() && !wasm_dir.is_dir() {
         return Err(Error::Runtime(
-            crate::runtime::Error::ProvidersDirUnavailable(wasm_dir),
+            crate::runtime::Error::ProvidersDirUnavailable(wasm_dir.to_path_buf()),
         ));
     }
 
@@ -50,41 +484,129 @@ pub async fn pull(providers: &[BuiltinProvider], all: bool) -> Result<(), Error>
         std::fs::create_dir_all(wasm_dir)?;
     }
 
-    let providers: &[BuiltinProvider] = if all || providers.is_empty() {
+    let providers: Vec<ProviderSpec> = if all || providers.is_empty() {
         ALL_PROVIDERS
+            .iter()
+            .map(|provider| ProviderSpec {
+                name: provider.name(),
+                version: None,
+            })
+            .collect()
     } else {
-        providers
+        providers.to_vec()
     };
+
+    let mut installed = InstalledProviders::read(wasm_dir)?;
     let mut errors = Vec::new();
-    for provider in providers {
-        if let Err(err) = fetch_provider(*provider).await {
-            errors.push(err)
+    for provider in &providers {
+        match fetch_provider(
+            registry,
+            wasm_dir,
+            provider,
+            mode,
+            &installed,
+            &mut verifier,
+        )
+        .await
+        {
+            Ok(Some(record)) => {
+                installed.providers.insert(
+                    module_name(&provider.name, provider.version.as_deref()),
+                    record,
+                );
+            }
+            Ok(None) => {}
+            Err(err) => errors.push(err),
         }
     }
+    installed.write(wasm_dir).await?;
+
     if !errors.is_empty() {
         return Err(Error::Multiple { errors });
     }
     Ok(())
 }
 
-async fn fetch_provider(provider: BuiltinProvider) -> Result<(), Error> {
-    let wasm_dir = providers_wasm_dir()?;
-    let provider_name = provider.name();
-    let location_url =
-        format!("https://github.com/fiberplane/proxy/raw/main/providers/{provider_name}.wasm");
+/// Check whether the installed provider module should be kept instead of
+/// installing `version` (if known) over it.
+///
+/// Fail if the module is installed and `mode` doesn't allow replacing it.
+fn keep_installed(
+    wasm_dir: &Path,
+    module: &str,
+    version: Option<&str>,
+    mode: InstallMode,
+    installed: &InstalledProviders,
+) -> Result<bool, Error> {
+    let target_path = wasm_dir.join(format!("{module}.wasm"));
+    if !target_path.exists() {
+        return Ok(false);
+    }
 
-    info!("Fetching {provider_name} provider at {location_url}");
+    match (mode, version) {
+        (InstallMode::Missing, _) => Err(Error::NoOverwrite {
+            provider: module.to_string(),
+            path: target_path,
+        }),
+        (InstallMode::Update, Some(version)) if version != LATEST_VERSION => {
+            let up_to_date = installed
+                .providers
+                .get(module)
+                .map_or(false, |installed| installed.version == version);
+            if up_to_date {
+                info!("{module} provider is already at version {version}");
+            }
+            Ok(up_to_date)
+        }
+        (InstallMode::Update, _) | (InstallMode::Force, _) => Ok(false),
+    }
+}
 
-    let bytes = reqwest::get(location_url).await?.bytes().await?;
+/// Download the provider into the wasm directory (as `<name>@<version>.wasm`
+/// when it's pinned to a release), once its checksum is verified.
+///
+/// Return the record of the installed provider, or `None` if the installed
+/// provider was kept.
+async fn fetch_provider(
+    registry: &Registry,
+    wasm_dir: &Path,
+    provider: &ProviderSpec,
+    mode: InstallMode,
+    installed: &InstalledProviders,
+    verifier: &mut Verifier,
+) -> Result<Option<InstalledProvider>, Error> {
+    let provider_name = provider.name.clone();
+    let version = provider
+        .version
+        .clone()
+        .unwrap_or_else(|| LATEST_VERSION.to_string());
 
-    let target_path = wasm_dir.join(format!("{provider_name}.wasm"));
-    if target_path.exists() {
-        return Err(Error::NoOverwrite {
-            provider: provider_name,
-            path: target_path,
-        });
+    let module = module_name(&provider_name, provider.version.as_deref());
+    if keep_installed(wasm_dir, &module, Some(&version), mode, installed)? {
+        return Ok(None);
     }
 
-    std::fs::write(target_path, bytes)?;
-    Ok(())
+    let file_name = format!("{provider_name}.wasm");
+    let location = registry.location(&version, &file_name);
+    info!("Fetching {provider_name} provider at {location}");
+
+    let bytes = match registry.fetch(&version, &file_name).await? {
+        Some(bytes) => bytes,
+        None => {
+            return Err(Error::NotFound {
+                provider: provider_name,
+                version,
+            })
+        }
+    };
+    verifier
+        .verify(&provider_name, &version, &file_name, &bytes)
+        .await?;
+
+    write_atomically(wasm_dir, &format!("{module}.wasm"), &bytes).await?;
+
+    Ok(Some(InstalledProvider {
+        version,
+        url: location,
+    }))
 }
@@ -0,0 +1,305 @@
+//! Offline bundles of providers, to install providers without network access
+//!
+//! A bundle is a gzipped tarball with a `manifest.json` listing the providers
+//! with their version and checksum, and the providers themselves in
+//! `providers/<name>.wasm` (`providers/<name>@<version>.wasm` for the providers
+//! pinned to a release). Signed bundles also have a `manifest.json.sig`
+//! with the detached ed25519 signature of the manifest, encoded in base64.
+
+use super::{
+    decode_signature, is_valid_module_name, keep_installed, parse_public_key, sha256_hex,
+    write_atomically, Error, InstallMode, InstalledProvider, InstalledProviders,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+use tracing::info;
+
+#[cfg(test)]
+mod tests;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SIGNATURE_FILE: &str = "manifest.json.sig";
+const PROVIDERS_DIR: &str = "providers/";
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifest {
+    pub format_version: u32,
+    pub providers: Vec<BundledProvider>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundledProvider {
+    pub name: String,
+    /// Release the provider was pulled from, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub sha256: String,
+}
+
+/// Pack the providers of `wasm_dir` into a bundle at `output`, and return the
+/// manifest of the bundle.
+///
+/// The manifest is signed with `signing_key` if one is given.
+pub async fn bundle(
+    wasm_dir: &Path,
+    output: &Path,
+    signing_key: Option<&Ed25519KeyPair>,
+) -> Result<BundleManifest, Error> {
+    let installed = InstalledProviders::read(wasm_dir)?;
+
+    let mut providers = BTreeMap::new();
+    let mut entries = fs::read_dir(wasm_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let name = match (path.file_stem(), path.extension()) {
+            (Some(name), Some(extension)) if extension == "wasm" => name.to_string_lossy(),
+            _ => continue,
+        };
+        if is_valid_module_name(&name) && path.is_file() {
+            providers.insert(name.into_owned(), fs::read(&path).await?);
+        }
+    }
+
+    let manifest = BundleManifest {
+        format_version: FORMAT_VERSION,
+        providers: providers
+            .iter()
+            .map(|(name, contents)| BundledProvider {
+                name: name.clone(),
+                version: installed
+                    .providers
+                    .get(name)
+                    .map(|provider| provider.version.clone()),
+                sha256: sha256_hex(contents),
+            })
+            .collect(),
+    };
+
+    let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let manifest_contents =
+        serde_json::to_vec_pretty(&manifest).expect("the manifest is serializable");
+    append_file(&mut archive, MANIFEST_FILE, &manifest_contents)?;
+    if let Some(signing_key) = signing_key {
+        let signature = base64::encode(signing_key.sign(&manifest_contents));
+        append_file(&mut archive, SIGNATURE_FILE, signature.as_bytes())?;
+    }
+    for (name, contents) in &providers {
+        append_file(
+            &mut archive,
+            &format!("{PROVIDERS_DIR}{name}.wasm"),
+            contents,
+        )?;
+    }
+    let contents = archive.into_inner()?.finish()?;
+    fs::write(output, contents).await?;
+
+    Ok(manifest)
+}
+
+fn append_file<W: std::io::Write>(
+    archive: &mut tar::Builder<W>,
+    path: &str,
+    contents: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, path, contents)
+}
+
+/// Check the bundle at `bundle_path` and install its providers into
+/// `wasm_dir`, and return the manifest of the bundle.
+///
+/// Nothing is installed unless every provider of the bundle matches the
+/// checksum of the manifest, and, if a public key is given, the manifest
+/// matches its signature.
+pub async fn install_bundle(
+    wasm_dir: &Path,
+    bundle_path: &Path,
+    mode: InstallMode,
+    public_key: Option<&str>,
+) -> Result<BundleManifest, Error> {
+    let public_key = public_key.map(parse_public_key).transpose()?;
+    let invalid_bundle = |message| Error::InvalidBundle {
+        path: bundle_path.to_path_buf(),
+        message,
+    };
+    let BundleContents {
+        manifest,
+        manifest_contents,
+        signature,
+        mut providers,
+    } = read_bundle(&fs::read(bundle_path).await?).map_err(invalid_bundle)?;
+
+    if let Some(public_key) = public_key {
+        let signature = signature.ok_or_else(|| {
+            invalid_bundle(format!(
+                "{SIGNATURE_FILE} not found, the bundle isn't signed"
+            ))
+        })?;
+        let signature = decode_signature(&signature)
+            .ok_or_else(|| invalid_bundle("the signature is not valid base64".to_string()))?;
+        UnparsedPublicKey::new(&ED25519, public_key)
+            .verify(&manifest_contents, &signature)
+            .map_err(|_| Error::InvalidSignature {
+                location: format!("{}:{MANIFEST_FILE}", bundle_path.display()),
+            })?;
+    }
+
+    for provider in &manifest.providers {
+        let actual = sha256_hex(&providers[&provider.name]);
+        if actual != provider.sha256 {
+            return Err(Error::ChecksumMismatch {
+                provider: provider.name.clone(),
+                expected: provider.sha256.clone(),
+                actual,
+            });
+        }
+    }
+
+    fs::create_dir_all(wasm_dir).await?;
+    let mut installed = InstalledProviders::read(wasm_dir)?;
+    let mut errors = Vec::new();
+    for provider in &manifest.providers {
+        match keep_installed(
+            wasm_dir,
+            &provider.name,
+            provider.version.as_deref(),
+            mode,
+            &installed,
+        ) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        }
+
+        info!("Installing {} provider from the bundle", provider.name);
+        let contents = providers
+            .remove(&provider.name)
+            .expect("bundled providers are checked");
+        write_atomically(wasm_dir, &format!("{}.wasm", provider.name), &contents).await?;
+        match &provider.version {
+            Some(version) => {
+                installed.providers.insert(
+                    provider.name.clone(),
+                    InstalledProvider {
+                        version: version.clone(),
+                        url: bundle_path.display().to_string(),
+                    },
+                );
+            }
+            None => {
+                installed.providers.remove(&provider.name);
+            }
+        }
+    }
+    installed.write(wasm_dir).await?;
+
+    if !errors.is_empty() {
+        return Err(Error::Multiple { errors });
+    }
+    Ok(manifest)
+}
+
+/// Manifest of a bundle, and the contents of its files
+struct BundleContents {
+    manifest: BundleManifest,
+    /// Contents of the manifest, as signed
+    manifest_contents: Vec<u8>,
+    signature: Option<Vec<u8>>,
+    /// Contents of the providers by name
+    providers: BTreeMap<String, Vec<u8>>,
+}
+
+/// Read the manifest and the providers of a bundle, and check that they match
+fn read_bundle(contents: &[u8]) -> Result<BundleContents, String> {
+    let mut archive = tar::Archive::new(GzDecoder::new(contents));
+    let mut manifest = None;
+    let mut signature = None;
+    let mut providers = BTreeMap::new();
+
+    for entry in archive.entries().map_err(|err| err.to_string())? {
+        let mut entry = entry.map_err(|err| err.to_string())?;
+        let entry_path: PathBuf = entry.path().map_err(|err| err.to_string())?.into_owned();
+        let entry_path = entry_path.to_string_lossy().into_owned();
+        if !entry.header().entry_type().is_file() {
+            return Err(format!("unexpected entry '{entry_path}'"));
+        }
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|err| err.to_string())?;
+        let duplicate = match entry_path.as_str() {
+            MANIFEST_FILE => {
+                let parsed: BundleManifest = serde_json::from_slice(&contents)
+                    .map_err(|err| format!("invalid manifest: {err}"))?;
+                if parsed.format_version != FORMAT_VERSION {
+                    return Err(format!(
+                        "unsupported bundle format version {}",
+                        parsed.format_version
+                    ));
+                }
+                manifest.replace((parsed, contents)).is_some()
+            }
+            SIGNATURE_FILE => signature.replace(contents).is_some(),
+            _ => match entry_path
+                .strip_prefix(PROVIDERS_DIR)
+                .and_then(|file_name| file_name.strip_suffix(".wasm"))
+            {
+                Some(name) if is_valid_module_name(name) => {
+                    providers.insert(name.to_string(), contents).is_some()
+                }
+                _ => return Err(format!("unexpected file '{entry_path}'")),
+            },
+        };
+        if duplicate {
+            return Err(format!("'{entry_path}' is in the bundle twice"));
+        }
+    }
+
+    let (manifest, manifest_contents) =
+        manifest.ok_or_else(|| format!("{MANIFEST_FILE} not found"))?;
+    let mut listed = BTreeSet::new();
+    for provider in &manifest.providers {
+        if !listed.insert(provider.name.as_str()) {
+            return Err(format!(
+                "provider '{}' is listed twice in the manifest",
+                provider.name
+            ));
+        }
+        if !providers.contains_key(&provider.name) {
+            return Err(format!(
+                "provider '{}' is listed in the manifest but missing from the bundle",
+                provider.name
+            ));
+        }
+    }
+    if let Some(name) = providers
+        .keys()
+        .find(|name| !listed.contains(name.as_str()))
+    {
+        return Err(format!("provider '{name}' is not listed in the manifest"));
+    }
+
+    Ok(BundleContents {
+        manifest,
+        manifest_contents,
+        signature,
+        providers,
+    })
+}
@@ -0,0 +1,261 @@
+use super::{bundle, install_bundle, BundledProvider, PROVIDERS_DIR};
+use crate::tasks::provider_manager::{
+    sha256_hex, Error, InstallMode, InstalledProvider, InstalledProviders,
+};
+use flate2::{write::GzEncoder, Compression};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::{fs, path::Path};
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn bundles_and_installs_providers() {
+    let source = TempDir::new().unwrap();
+    let source_dir = source.path();
+    fs::write(source_dir.join("prometheus.wasm"), "prometheus module").unwrap();
+    fs::write(source_dir.join("prometheus@v2.4.0.wasm"), "v2.4.0 module").unwrap();
+    fs::write(source_dir.join("acme-logs.wasm"), "acme module").unwrap();
+    fs::write(source_dir.join("notes.txt"), "not a provider").unwrap();
+    fs::write(
+        source_dir.join("installed.json"),
+        r#"{ "providers": {
+            "prometheus": { "version": "v2.3.0", "url": "https://example.com" },
+            "prometheus@v2.4.0": { "version": "v2.4.0", "url": "https://example.com" }
+        } }"#,
+    )
+    .unwrap();
+    let bundle_path = source_dir.join("providers.tar.gz");
+
+    let manifest = bundle(source_dir, &bundle_path, None).await.unwrap();
+    assert_eq!(
+        manifest.providers,
+        vec![
+            BundledProvider {
+                name: "acme-logs".to_string(),
+                version: None,
+                sha256: sha256_hex(b"acme module"),
+            },
+            BundledProvider {
+                name: "prometheus".to_string(),
+                version: Some("v2.3.0".to_string()),
+                sha256: sha256_hex(b"prometheus module"),
+            },
+            BundledProvider {
+                name: "prometheus@v2.4.0".to_string(),
+                version: Some("v2.4.0".to_string()),
+                sha256: sha256_hex(b"v2.4.0 module"),
+            },
+        ]
+    );
+
+    let target = TempDir::new().unwrap();
+    let target_dir = target.path();
+    fs::write(target_dir.join("prometheus.wasm"), "old module").unwrap();
+    let err = install_bundle(target_dir, &bundle_path, InstallMode::Missing, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Multiple { .. }));
+    assert_eq!(
+        fs::read_to_string(target_dir.join("prometheus.wasm")).unwrap(),
+        "old module"
+    );
+    assert_eq!(
+        fs::read_to_string(target_dir.join("acme-logs.wasm")).unwrap(),
+        "acme module"
+    );
+    // Pinned releases are installed next to the latest version
+    assert_eq!(
+        fs::read_to_string(target_dir.join("prometheus@v2.4.0.wasm")).unwrap(),
+        "v2.4.0 module"
+    );
+
+    install_bundle(target_dir, &bundle_path, InstallMode::Update, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(target_dir.join("prometheus.wasm")).unwrap(),
+        "prometheus module"
+    );
+    assert_eq!(
+        InstalledProviders::read(target_dir)
+            .unwrap()
+            .providers
+            .get("prometheus"),
+        Some(&InstalledProvider {
+            version: "v2.3.0".to_string(),
+            url: bundle_path.display().to_string(),
+        })
+    );
+}
+
+/// Write a bundle with the given manifest and files
+fn write_bundle(path: &Path, manifest: &str, files: &[(&str, &str)]) {
+    let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut entries = vec![("manifest.json".to_string(), manifest)];
+    entries.extend(
+        files
+            .iter()
+            .map(|(name, contents)| (format!("{PROVIDERS_DIR}{name}"), *contents)),
+    );
+    for (name, contents) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, name, contents.as_bytes())
+            .unwrap();
+    }
+    fs::write(path, archive.into_inner().unwrap().finish().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_invalid_bundles() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    let target_dir = dir.join("providers");
+    let bundle_path = dir.join("bundle.tar.gz");
+
+    let manifest = format!(
+        r#"{{ "formatVersion": 1, "providers": [{{ "name": "prometheus", "sha256": "{}" }}] }}"#,
+        sha256_hex(b"prometheus module")
+    );
+    write_bundle(
+        &bundle_path,
+        &manifest,
+        &[("prometheus.wasm", "tampered module")],
+    );
+    let err = install_bundle(&target_dir, &bundle_path, InstallMode::Force, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::ChecksumMismatch { .. }));
+    assert!(!target_dir.join("prometheus.wasm").exists());
+
+    write_bundle(
+        &bundle_path,
+        &manifest,
+        &[
+            ("prometheus.wasm", "prometheus module"),
+            ("nested/module.wasm", "nested module"),
+        ],
+    );
+    let err = install_bundle(&target_dir, &bundle_path, InstallMode::Force, None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidBundle { .. }));
+
+    write_bundle(
+        &bundle_path,
+        &manifest,
+        &[
+            ("prometheus.wasm", "prometheus module"),
+            ("loki.wasm", "loki module"),
+        ],
+    );
+    let err = install_bundle(&target_dir, &bundle_path, InstallMode::Force, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Invalid bundle '{}': provider 'loki' is not listed in the manifest",
+            bundle_path.display()
+        )
+    );
+    assert!(!target_dir.exists());
+
+    let duplicate_manifest = format!(
+        r#"{{ "formatVersion": 1, "providers": [{{ "name": "prometheus", "sha256": "{checksum}" }}, {{ "name": "prometheus", "sha256": "{checksum}" }}] }}"#,
+        checksum = sha256_hex(b"prometheus module")
+    );
+    write_bundle(
+        &bundle_path,
+        &duplicate_manifest,
+        &[("prometheus.wasm", "prometheus module")],
+    );
+    let err = install_bundle(&target_dir, &bundle_path, InstallMode::Force, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Invalid bundle '{}': provider 'prometheus' is listed twice in the manifest",
+            bundle_path.display()
+        )
+    );
+
+    write_bundle(
+        &bundle_path,
+        &manifest,
+        &[
+            ("prometheus.wasm", "prometheus module"),
+            ("prometheus.wasm", "tampered module"),
+        ],
+    );
+    let err = install_bundle(&target_dir, &bundle_path, InstallMode::Force, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Invalid bundle '{}': 'providers/prometheus.wasm' is in the bundle twice",
+            bundle_path.display()
+        )
+    );
+    assert!(!target_dir.exists());
+}
+
+#[tokio::test]
+async fn checks_the_signature_of_signed_bundles() {
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+    let public_key = base64::encode(key_pair.public_key().as_ref());
+    let other_public_key = base64::encode(
+        Ed25519KeyPair::from_seed_unchecked(&[8; 32])
+            .unwrap()
+            .public_key()
+            .as_ref(),
+    );
+    let source = TempDir::new().unwrap();
+    fs::write(source.path().join("prometheus.wasm"), "prometheus module").unwrap();
+    let target = TempDir::new().unwrap();
+    let signed_bundle = target.path().join("signed.tar.gz");
+    let unsigned_bundle = target.path().join("unsigned.tar.gz");
+    bundle(source.path(), &signed_bundle, Some(&key_pair))
+        .await
+        .unwrap();
+    bundle(source.path(), &unsigned_bundle, None).await.unwrap();
+    let wasm_dir = target.path().join("providers");
+
+    let err = install_bundle(
+        &wasm_dir,
+        &unsigned_bundle,
+        InstallMode::Force,
+        Some(&public_key),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidBundle { .. }));
+
+    let err = install_bundle(
+        &wasm_dir,
+        &signed_bundle,
+        InstallMode::Force,
+        Some(&other_public_key),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature { .. }));
+    assert!(!wasm_dir.exists());
+
+    install_bundle(
+        &wasm_dir,
+        &signed_bundle,
+        InstallMode::Force,
+        Some(&public_key),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus.wasm")).unwrap(),
+        "prometheus module"
+    );
+}
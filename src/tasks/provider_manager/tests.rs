@@ -0,0 +1,474 @@
+use super::{
+    fetch_provider, pull, sha256_hex, write_atomically, Error, InstallMode, InstalledProvider,
+    InstalledProviders, Registry, Verifier,
+};
+use crate::cli::ProviderSpec;
+use httpmock::{prelude::*, Mock};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::{fs, path::PathBuf};
+use tempfile::TempDir;
+
+/// Publish the checksums manifest of a release, which lists `module` as the
+/// contents of the prometheus provider
+async fn publish_checksums<'a>(server: &'a MockServer, version: &str, module: &str) -> Mock<'a> {
+    let manifest = format!("{}  prometheus.wasm\n", sha256_hex(module.as_bytes()));
+    server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path(format!("/raw/{version}/providers/SHA256SUMS"));
+            then.status(200).body(manifest);
+        })
+        .await
+}
+
+fn registry(server: &MockServer) -> Registry {
+    Registry::Http(server.url(""))
+}
+
+fn prometheus(version: Option<&str>) -> ProviderSpec {
+    ProviderSpec {
+        name: "prometheus".to_string(),
+        version: version.map(str::to_string),
+    }
+}
+
+#[tokio::test]
+async fn pulls_pinned_versions() {
+    let server = MockServer::start_async().await;
+    let release = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v2.3.0/providers/prometheus.wasm");
+            then.status(200).body("v2.3.0 module");
+        })
+        .await;
+    let missing = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v9.9.9/providers/prometheus.wasm");
+            then.status(404).body("Not Found");
+        })
+        .await;
+    let _checksums = publish_checksums(&server, "v2.3.0", "v2.3.0 module").await;
+    let mut verifier = Verifier::new(&registry(&server), None, false).unwrap();
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    fs::write(wasm_dir.join("prometheus.wasm"), "latest module").unwrap();
+
+    let record = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        record,
+        Some(InstalledProvider {
+            version: "v2.3.0".to_string(),
+            url: server.url("/raw/v2.3.0/providers/prometheus.wasm"),
+        })
+    );
+    // The pinned release is installed next to the latest version
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus@v2.3.0.wasm")).unwrap(),
+        "v2.3.0 module"
+    );
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus.wasm")).unwrap(),
+        "latest module"
+    );
+
+    // The error page of a missing release is never installed
+    let err = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v9.9.9")),
+        InstallMode::Force,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::NotFound { .. }));
+    assert!(!wasm_dir.join("prometheus@v9.9.9.wasm").exists());
+
+    release.assert_async().await;
+    missing.assert_async().await;
+}
+
+#[tokio::test]
+async fn replaces_installed_providers_on_update() {
+    let server = MockServer::start_async().await;
+    let latest = server
+        .mock_async(|when, then| {
+            when.method(GET).path("/raw/main/providers/prometheus.wasm");
+            then.status(200).body("latest module");
+        })
+        .await;
+    let release = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v2.3.0/providers/prometheus.wasm");
+            then.status(200).body("v2.3.0 module");
+        })
+        .await;
+    let _checksums = publish_checksums(&server, "v2.3.0", "v2.3.0 module").await;
+    let _latest_checksums = publish_checksums(&server, "main", "latest module").await;
+    let mut verifier = Verifier::new(&registry(&server), None, false).unwrap();
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    fs::write(wasm_dir.join("prometheus.wasm"), "installed module").unwrap();
+    fs::write(
+        wasm_dir.join("prometheus@v2.3.0.wasm"),
+        "installed v2.3.0 module",
+    )
+    .unwrap();
+
+    let mut installed = InstalledProviders::default();
+    installed.providers.insert(
+        "prometheus".to_string(),
+        InstalledProvider {
+            version: "main".to_string(),
+            url: server.url("/raw/main/providers/prometheus.wasm"),
+        },
+    );
+    installed.providers.insert(
+        "prometheus@v2.3.0".to_string(),
+        InstalledProvider {
+            version: "v2.3.0".to_string(),
+            url: server.url("/raw/v2.3.0/providers/prometheus.wasm"),
+        },
+    );
+
+    let err = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(None),
+        InstallMode::Missing,
+        &installed,
+        &mut verifier,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::NoOverwrite { .. }));
+
+    // Already at the requested version
+    let record = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Update,
+        &installed,
+        &mut verifier,
+    )
+    .await
+    .unwrap();
+    assert_eq!(record, None);
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus@v2.3.0.wasm")).unwrap(),
+        "installed v2.3.0 module"
+    );
+
+    let record = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Force,
+        &installed,
+        &mut verifier,
+    )
+    .await
+    .unwrap();
+    assert!(record.is_some());
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus@v2.3.0.wasm")).unwrap(),
+        "v2.3.0 module"
+    );
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus.wasm")).unwrap(),
+        "installed module"
+    );
+
+    // The latest version is always updated
+    let record = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(None),
+        InstallMode::Update,
+        &installed,
+        &mut verifier,
+    )
+    .await
+    .unwrap();
+    assert_eq!(record.unwrap().version, "main");
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus.wasm")).unwrap(),
+        "latest module"
+    );
+
+    latest.assert_async().await;
+    release.assert_hits_async(1).await;
+}
+
+#[tokio::test]
+async fn refuses_providers_that_dont_match_their_checksum() {
+    let server = MockServer::start_async().await;
+    let _module = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v2.3.0/providers/prometheus.wasm");
+            then.status(200).body("tampered module");
+        })
+        .await;
+    let _checksums = publish_checksums(&server, "v2.3.0", "v2.3.0 module").await;
+    let mut verifier = Verifier::new(&registry(&server), None, false).unwrap();
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+
+    let err = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::ChecksumMismatch { .. }));
+    assert!(!wasm_dir.join("prometheus@v2.3.0.wasm").exists());
+}
+
+#[tokio::test]
+async fn checks_the_signature_of_the_checksums() {
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+    let public_key = base64::encode(key_pair.public_key().as_ref());
+    let other_public_key = base64::encode(
+        Ed25519KeyPair::from_seed_unchecked(&[8; 32])
+            .unwrap()
+            .public_key()
+            .as_ref(),
+    );
+
+    let server = MockServer::start_async().await;
+    let _module = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v2.3.0/providers/prometheus.wasm");
+            then.status(200).body("v2.3.0 module");
+        })
+        .await;
+    let manifest = format!("{}  prometheus.wasm\n", sha256_hex(b"v2.3.0 module"));
+    let signature = base64::encode(key_pair.sign(manifest.as_bytes()));
+    let _checksums = server
+        .mock_async(|when, then| {
+            when.method(GET).path("/raw/v2.3.0/providers/SHA256SUMS");
+            then.status(200).body(manifest);
+        })
+        .await;
+    let _signature = server
+        .mock_async(|when, then| {
+            when.method(GET)
+                .path("/raw/v2.3.0/providers/SHA256SUMS.sig");
+            then.status(200).body(signature);
+        })
+        .await;
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+
+    let mut verifier = Verifier::new(&registry(&server), Some(&other_public_key), false).unwrap();
+    let err = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::InvalidSignature { .. }));
+    assert!(!wasm_dir.join("prometheus@v2.3.0.wasm").exists());
+
+    let mut verifier = Verifier::new(&registry(&server), Some(&public_key), false).unwrap();
+    let record = fetch_provider(
+        &registry(&server),
+        wasm_dir,
+        &prometheus(Some("v2.3.0")),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap();
+    assert!(record.is_some());
+    assert!(wasm_dir.join("prometheus@v2.3.0.wasm").exists());
+}
+
+#[tokio::test]
+async fn pulls_releases_without_checksums_only_when_allowed() {
+    let public_key = base64::encode(
+        Ed25519KeyPair::from_seed_unchecked(&[7; 32])
+            .unwrap()
+            .public_key()
+            .as_ref(),
+    );
+    let server = MockServer::start_async().await;
+    let _module = server
+        .mock_async(|when, then| {
+            when.method(GET).path("/raw/main/providers/prometheus.wasm");
+            then.status(200).body("latest module");
+        })
+        .await;
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+
+    let err = pull(
+        wasm_dir,
+        &registry(&server),
+        &[prometheus(None)],
+        false,
+        InstallMode::Missing,
+        None,
+        false,
+    )
+    .await
+    .unwrap_err();
+    match err {
+        Error::Multiple { errors } => {
+            assert!(matches!(errors[..], [Error::Unverified { .. }]))
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    assert!(!wasm_dir.join("prometheus.wasm").exists());
+
+    // The manifest is always required to check its signature
+    let err = pull(
+        wasm_dir,
+        &registry(&server),
+        &[prometheus(None)],
+        false,
+        InstallMode::Missing,
+        Some(&public_key),
+        true,
+    )
+    .await
+    .unwrap_err();
+    match err {
+        Error::Multiple { errors } => {
+            assert!(matches!(errors[..], [Error::ManifestNotFound { .. }]))
+        }
+        err => panic!("unexpected error: {}", err),
+    }
+    assert!(!wasm_dir.join("prometheus.wasm").exists());
+
+    pull(
+        wasm_dir,
+        &registry(&server),
+        &[prometheus(None)],
+        false,
+        InstallMode::Missing,
+        None,
+        true,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("prometheus.wasm")).unwrap(),
+        "latest module"
+    );
+}
+
+#[tokio::test]
+async fn writes_files_atomically() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("prometheus.wasm"), "old module").unwrap();
+
+    write_atomically(dir, "prometheus.wasm", b"new module")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dir.join("prometheus.wasm")).unwrap(),
+        "new module"
+    );
+    // Only the target file is left in the directory
+    let files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files, vec!["prometheus.wasm"]);
+}
+
+#[tokio::test]
+async fn pulls_third_party_providers_from_local_registries() {
+    let registry_dir = TempDir::new().unwrap();
+    let registry_dir = registry_dir.path();
+    let release_dir = registry_dir.join("raw").join("v1.0.0").join("providers");
+    fs::create_dir_all(&release_dir).unwrap();
+    fs::write(release_dir.join("acme-logs.wasm"), "acme module").unwrap();
+    fs::write(
+        release_dir.join("SHA256SUMS"),
+        format!("{} *acme-logs.wasm\n", sha256_hex(b"acme module")),
+    )
+    .unwrap();
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+
+    let registry: Registry = format!("file://{}", registry_dir.display())
+        .parse()
+        .unwrap();
+    assert_eq!(registry, Registry::Local(registry_dir.to_path_buf()));
+    let mut verifier = Verifier::new(&registry, None, false).unwrap();
+    let record = fetch_provider(
+        &registry,
+        wasm_dir,
+        &"acme-logs@v1.0.0".parse().unwrap(),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        record.url,
+        release_dir.join("acme-logs.wasm").display().to_string()
+    );
+    assert_eq!(
+        fs::read_to_string(wasm_dir.join("acme-logs@v1.0.0.wasm")).unwrap(),
+        "acme module"
+    );
+
+    let err = fetch_provider(
+        &registry,
+        wasm_dir,
+        &"acme-metrics@v1.0.0".parse().unwrap(),
+        InstallMode::Missing,
+        &InstalledProviders::default(),
+        &mut verifier,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, Error::NotFound { .. }));
+}
+
+#[test]
+fn registry_parsing() {
+    assert_eq!(
+        "https://mirror.internal/fiberplane/proxy/"
+            .parse::<Registry>()
+            .unwrap(),
+        Registry::Http("https://mirror.internal/fiberplane/proxy".to_string())
+    );
+    assert_eq!(
+        "/srv/providers".parse::<Registry>().unwrap(),
+        Registry::Local(PathBuf::from("/srv/providers"))
+    );
+    "".parse::<Registry>().expect_err("empty registry");
+}
@@ -0,0 +1,104 @@
+//! Task to reload the providers when their wasm module changes on disk
+
+use super::service::ProxyService;
+use futures::{select, FutureExt};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::fs;
+use tokio::sync::broadcast;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, info, warn};
+
+#[cfg(test)]
+mod tests;
+
+/// Size and modification time of a wasm module, to detect changes (`None` if
+/// the module doesn't exist)
+type Fingerprint = Option<(u64, Option<SystemTime>)>;
+
+/// Check the provider modules of the data sources every `poll_interval` and
+/// reload the providers whose module was added or changed since the previous
+/// check.
+///
+/// Only the modules the data sources resolve to are checked, so providers
+/// outside of the wasm directory (set with `providerPath`) are reloaded too. A
+/// zero `poll_interval` disables reloading the providers.
+pub async fn watch_providers(
+    service: ProxyService,
+    poll_interval: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    if poll_interval.is_zero() {
+        return;
+    }
+    let mut poll_interval = interval(poll_interval);
+    poll_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut modules = scan(service.provider_modules().await).await;
+    loop {
+        select! {
+            _ = poll_interval.tick().fuse() => {},
+            _ = shutdown.recv().fuse() => break,
+        };
+
+        let new_modules = scan(service.provider_modules().await).await;
+        let changed = changed_modules(&modules, &new_modules);
+        for (module, fingerprint) in &modules {
+            if fingerprint.is_some() && new_modules.get(module) == Some(&None) {
+                warn!(module = %module.display(), "Provider module was removed, keeping the loaded provider");
+            }
+        }
+        modules = new_modules;
+
+        if !changed.is_empty() {
+            info!(
+                "Reloading providers: {}",
+                changed
+                    .iter()
+                    .map(|module| module.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            service.reload_providers(changed).await;
+        }
+    }
+    debug!("stopped watching providers");
+}
+
+/// Fingerprints of the given wasm modules, by path
+async fn scan(modules: impl IntoIterator<Item = PathBuf>) -> HashMap<PathBuf, Fingerprint> {
+    let mut fingerprints = HashMap::new();
+    for module in modules {
+        let fingerprint = match fs::metadata(&module).await {
+            Ok(metadata) if metadata.is_file() => Some((metadata.len(), metadata.modified().ok())),
+            _ => None,
+        };
+        fingerprints.insert(module, fingerprint);
+    }
+    fingerprints
+}
+
+/// Paths of the modules that were added or changed, sorted.
+///
+/// Modules that weren't checked before belong to data sources that were just
+/// added, whose providers are loaded with them.
+fn changed_modules(
+    previous: &HashMap<PathBuf, Fingerprint>,
+    current: &HashMap<PathBuf, Fingerprint>,
+) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = current
+        .iter()
+        .filter(|(module, fingerprint)| {
+            fingerprint.is_some()
+                && previous
+                    .get(*module)
+                    .map_or(false, |previous| previous != *fingerprint)
+        })
+        .map(|(module, _)| module.clone())
+        .collect();
+    changed.sort();
+    changed
+}
@@ -0,0 +1,48 @@
+use super::{changed_modules, scan};
+use std::fs;
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn detects_added_and_changed_modules() {
+    let wasm_dir = TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    // A locally built provider, outside of the wasm directory
+    let build_dir = TempDir::new().unwrap();
+    let local_module = build_dir.path().join("prometheus.wasm");
+    fs::write(wasm_dir.join("prometheus.wasm"), "prometheus module").unwrap();
+    fs::write(wasm_dir.join("loki.wasm"), "loki module").unwrap();
+    fs::write(&local_module, "local module").unwrap();
+    let modules = vec![
+        wasm_dir.join("prometheus.wasm"),
+        wasm_dir.join("loki.wasm"),
+        wasm_dir.join("prometheus@v2.4.0.wasm"),
+        local_module.clone(),
+    ];
+
+    let before = scan(modules.clone()).await;
+    assert_eq!(before.len(), 4);
+    assert_eq!(before[&wasm_dir.join("prometheus@v2.4.0.wasm")], None);
+
+    fs::write(wasm_dir.join("prometheus.wasm"), "new prometheus module").unwrap();
+    fs::write(wasm_dir.join("prometheus@v2.4.0.wasm"), "v2.4.0 module").unwrap();
+    fs::write(&local_module, "rebuilt local module").unwrap();
+    fs::remove_file(wasm_dir.join("loki.wasm")).unwrap();
+    // Modules of data sources added since the previous check are loaded with
+    // their data sources
+    fs::write(wasm_dir.join("sentry.wasm"), "sentry module").unwrap();
+    let after = scan(
+        modules
+            .into_iter()
+            .chain(Some(wasm_dir.join("sentry.wasm"))),
+    )
+    .await;
+
+    let mut expected = vec![
+        wasm_dir.join("prometheus.wasm"),
+        wasm_dir.join("prometheus@v2.4.0.wasm"),
+        local_module,
+    ];
+    expected.sort();
+    assert_eq!(changed_modules(&before, &after), expected);
+    assert!(changed_modules(&after, &after).is_empty());
+}
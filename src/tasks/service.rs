@@ -1,5 +1,10 @@
-use super::metrics::{metrics_export, CONCURRENT_QUERIES, QUERIES_DURATION_SECONDS, QUERIES_TOTAL};
+use super::metrics::{
+    metrics_export, CONCURRENT_QUERIES, QUERIES_DURATION_SECONDS, QUERIES_REJECTED_TOTAL,
+    QUERIES_TIMEOUTS_TOTAL, QUERIES_TOTAL, QUERIES_WAIT_SECONDS, QUEUED_QUERIES,
+};
+use super::provider_manager::{is_valid_provider_version, module_name};
 use super::tokio_tungstenite_reconnect::ReconnectingWebSocket;
+use crate::interval::IntervalDuration;
 use anyhow::{anyhow, Context, Result};
 use fiberplane::base64uuid::Base64Uuid;
 use fiberplane::models::providers::{Error, STATUS_MIME_TYPE, STATUS_QUERY_TYPE};
@@ -13,36 +18,291 @@ use http::{Method, Request, Response, StatusCode};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Server};
 use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::{Map, Value};
-use std::collections::HashMap;
-use std::{convert::Infallible, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{convert::Infallible, future::Future, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-use tokio::sync::Mutex;
 use tokio::sync::{broadcast::Sender, watch};
-use tokio::{fs, time::interval};
+use tokio::sync::{Mutex, Notify, OwnedSemaphorePermit, RwLock};
+use tokio::{
+    fs,
+    time::{interval, interval_at, Instant},
+};
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info, info_span, instrument, trace, warn, Instrument, Span};
 use url::Url;
 
-mod bindings;
+pub(crate) mod bindings;
+mod blocking_pool;
+mod concurrency;
+mod local_api;
+mod protocol_version;
 mod status_check;
 #[cfg(test)]
 mod tests;
 
-use status_check::DataSourceCheckTask;
+use blocking_pool::BlockingPool;
+use concurrency::ConcurrencyLimit;
+use protocol_version::DEFAULT_PROTOCOL_VERSION;
+pub(crate) use protocol_version::{detect_protocol_version, exports_config_schema};
+pub use status_check::StatusCheckConfig;
+use status_check::{DataSourceCheckTask, DEFAULT_BACKOFF_FACTOR, DEFAULT_INITIAL_RETRY_DELAY};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct ProxyDataSource {
     pub name: Name,
     pub provider_type: String,
     pub config: Map<String, Value>,
     pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_check: Option<StatusCheckConfig>,
+    /// Overrides the global query timeout for this data source ("0s" disables it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_timeout: Option<IntervalDuration>,
+    /// Overrides the maximum number of queries running at the same time
+    /// against this data source
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_max_concurrent_queries"
+    )]
+    pub max_concurrent_queries: Option<usize>,
+    /// Path to the provider module to use instead of the one of the provider
+    /// type, relative to the wasm directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_path: Option<PathBuf>,
+    /// Version of the provider to use, installed as
+    /// `<provider type>@<version>.wasm` in the wasm directory
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_provider_version"
+    )]
+    pub provider_version: Option<String>,
+    /// Protocol version of the provider, which is detected from its module
+    /// when not set
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_protocol_version"
+    )]
+    pub protocol_version: Option<u8>,
+}
+
+fn deserialize_provider_version<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let version = String::deserialize(deserializer)?;
+    if !is_valid_provider_version(&version) {
+        return Err(de::Error::custom(format!(
+            "invalid provider version '{version}', expected a release like \"v2.3.0\""
+        )));
+    }
+    Ok(Some(version))
+}
+
+fn deserialize_protocol_version<'de, D>(deserializer: D) -> Result<Option<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match u8::deserialize(deserializer)? {
+        version @ (1 | 2) => Ok(Some(version)),
+        version => Err(de::Error::custom(format!(
+            "unsupported protocol version {version}, expected 1 or 2"
+        ))),
+    }
+}
+
+fn deserialize_max_concurrent_queries<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match usize::deserialize(deserializer)? {
+        0 => Err(de::Error::custom(
+            "the maximum number of concurrent queries must be greater than 0",
+        )),
+        max_concurrent => Ok(Some(max_concurrent)),
+    }
+}
+
+/// Limits applied to the queries sent to the providers
+#[derive(Debug, Clone)]
+pub struct QueryLimits {
+    /// Maximum duration of a provider invocation, unless the data source
+    /// overrides it
+    pub timeout: Option<Duration>,
+    /// Maximum number of queries running at the same time, for all data
+    /// sources together
+    pub max_concurrent_queries: Option<usize>,
+    /// Maximum number of queries running at the same time against a single
+    /// data source, unless the data source overrides it
+    pub max_concurrent_queries_per_data_source: Option<usize>,
+    /// Maximum number of queries waiting for a free slot (globally, and for
+    /// each data source) before new queries are rejected
+    pub max_queued_queries: usize,
+    /// Maximum number of synchronous provider calls (formatting cells,
+    /// extracting data, getting the config schema) running at the same time
+    pub max_blocking_calls: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            max_concurrent_queries: None,
+            max_concurrent_queries_per_data_source: None,
+            max_queued_queries: 100,
+            max_blocking_calls: 4,
+        }
+    }
+}
+
+/// Configuration of the HTTP server of the daemon
+#[derive(Debug, Clone)]
+pub struct HttpServerConfig {
+    pub listen_address: SocketAddr,
+    /// Serve the local API, to call the providers of the data sources over
+    /// HTTP without going through the relay
+    pub local_api: bool,
+    /// Token that requests to the local API must give as a bearer token
+    pub local_api_token: Option<String>,
+}
+
+impl HttpServerConfig {
+    /// Refuse to serve the local API without a token on an address other
+    /// hosts can reach
+    pub fn check(&self) -> Result<()> {
+        let has_token = self
+            .local_api_token
+            .as_deref()
+            .map_or(false, |token| !token.is_empty());
+        if self.local_api && !has_token && !self.listen_address.ip().is_loopback() {
+            return Err(anyhow!(
+                "--local-api requires --local-api-token when listening on {}, which isn't a loopback address",
+                self.listen_address
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ProxyDataSource {
+    /// A data source using the defaults of the daemon for everything but its
+    /// provider and config
+    pub fn new(name: Name, provider_type: String, config: Map<String, Value>) -> Self {
+        Self {
+            name,
+            provider_type,
+            config,
+            description: None,
+            status_check: None,
+            query_timeout: None,
+            max_concurrent_queries: None,
+            provider_path: None,
+            provider_version: None,
+            protocol_version: None,
+        }
+    }
+
+    /// Path of the module of the data source's provider, which is
+    /// `<provider type>.wasm` in the wasm directory by default
+    pub(crate) fn provider_module(&self, wasm_dir: &Path) -> PathBuf {
+        match (&self.provider_path, &self.provider_version) {
+            (Some(path), _) => wasm_dir.join(path),
+            (None, version) => wasm_dir.join(format!(
+                "{}.wasm",
+                module_name(&self.provider_type, version.as_deref())
+            )),
+        }
+    }
+
+    /// Protocol version to use with the provider of the data source: the one
+    /// set on the data source, else the one detected from its module
+    pub(crate) fn protocol_version(&self, wasm_dir: &Path, wasm_modules: &WasmModules) -> u8 {
+        let detected = match wasm_modules.get(&self.provider_module(wasm_dir)) {
+            Some(Ok(module)) => module.protocol_version,
+            _ => None,
+        };
+        self.protocol_version
+            .or(detected)
+            .unwrap_or(DEFAULT_PROTOCOL_VERSION)
+    }
+
+    /// Whether the data source neither sets a protocol version nor can use the
+    /// one detected from its provider module, and so uses the default one
+    pub(crate) fn has_undetected_protocol_version(
+        &self,
+        wasm_dir: &Path,
+        wasm_modules: &WasmModules,
+    ) -> bool {
+        self.protocol_version.is_none()
+            && matches!(
+                wasm_modules.get(&self.provider_module(wasm_dir)),
+                Some(Ok(WasmModule {
+                    protocol_version: None,
+                    ..
+                }))
+            )
+    }
+
+    /// Whether the status of the data source should be checked at all
+    pub(crate) fn status_check_enabled(&self) -> bool {
+        self.status_check
+            .as_ref()
+            .and_then(|status_check| status_check.enabled)
+            .unwrap_or(true)
+    }
+
+    /// Interval between two status checks of the data source
+    fn status_check_interval(&self, default: Duration) -> Duration {
+        self.status_check
+            .as_ref()
+            .and_then(|status_check| status_check.interval)
+            .map_or(default, |interval| interval.0)
+    }
+
+    /// Maximum duration of a provider invocation for this data source
+    pub(crate) fn query_timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        match self.query_timeout {
+            Some(IntervalDuration(timeout)) if timeout.is_zero() => None,
+            Some(IntervalDuration(timeout)) => Some(timeout),
+            None => default,
+        }
+    }
+
+    /// Create a task to check the status of the data source, retrying on
+    /// failure until the next scheduled check
+    fn status_check_task(&self, default_interval: Duration) -> DataSourceCheckTask {
+        let status_check = self.status_check.clone().unwrap_or_default();
+        DataSourceCheckTask::new(
+            self.name.clone(),
+            self.status_check_interval(default_interval),
+            status_check
+                .initial_retry_delay
+                .map_or(DEFAULT_INITIAL_RETRY_DELAY, |delay| delay.0),
+            status_check
+                .backoff_factor
+                .unwrap_or(DEFAULT_BACKOFF_FACTOR),
+        )
+    }
+}
+
+/// A compiled provider module
+#[derive(Clone)]
+pub(crate) struct WasmModule {
+    pub(crate) runtime: Arc<Runtime>,
+    /// Protocol version detected from the exports of the module, if any
+    pub(crate) protocol_version: Option<u8>,
+    /// Whether the module exports the function returning its config schema
+    pub(crate) has_config_schema: bool,
 }
 
-pub(crate) type WasmModules = HashMap<String, Result<Runtime, Error>>;
-const V1_PROVIDERS: &[&str] = &["elasticsearch", "loki"];
+/// Loaded providers, by path of their module
+pub(crate) type WasmModules = HashMap<PathBuf, Result<WasmModule, Error>>;
 
 static STATUS_REQUEST_V1: Lazy<Vec<u8>> =
     Lazy::new(|| rmp_serde::to_vec_named(&LegacyProviderRequest::Status).unwrap());
@@ -67,57 +327,107 @@ pub struct ProxyService {
     pub(crate) inner: Arc<Inner>,
 }
 
-pub(crate) struct Inner {
+/// Where the proxy connects to Fiberplane, and how it authenticates
+struct Relay {
     endpoint: Url,
     token: String,
-    pub(crate) data_sources: HashMap<Name, ProxyDataSource>,
+}
+
+pub(crate) struct Inner {
+    /// `None` for a service that only runs the providers locally
+    relay: Option<Relay>,
+    pub(crate) data_sources: RwLock<HashMap<Name, ProxyDataSource>>,
     data_sources_state: Mutex<HashMap<Name, UpsertProxyDataSource>>,
-    wasm_modules: WasmModules,
+    /// When the status of each data source is due to be checked next
+    next_status_checks: Mutex<HashMap<Name, Instant>>,
+    /// Notified when the data sources are reloaded, to check their status
+    /// and send them to the relay
+    data_sources_changed: Notify,
+    wasm_dir: PathBuf,
+    wasm_modules: RwLock<WasmModules>,
     max_retries: u32,
-    listen_address: Option<SocketAddr>,
+    http_server: Option<HttpServerConfig>,
     status_check_interval: Duration,
+    query_limits: QueryLimits,
+    global_query_slots: Option<ConcurrencyLimit>,
+    /// Query slots of the data sources that limit their concurrent queries,
+    /// created when they are first queried
+    query_slots: Mutex<HashMap<Name, Arc<ConcurrencyLimit>>>,
+    blocking_pool: BlockingPool,
 }
 
 impl ProxyService {
     /// Load the provider wasm files from the given directory and create a new Proxy instance
+    #[allow(clippy::too_many_arguments)]
     pub async fn init(
         api_base: Url,
         token: ProxyToken,
         wasm_dir: &Path,
         data_sources: Vec<ProxyDataSource>,
         max_retries: u32,
-        listen_address: Option<SocketAddr>,
+        http_server: Option<HttpServerConfig>,
         status_check_interval: Duration,
+        query_limits: QueryLimits,
     ) -> Self {
-        let data_sources: HashMap<Name, ProxyDataSource> = data_sources
-            .into_iter()
-            .map(|data_source| (data_source.name.clone(), (data_source)))
-            .collect();
-        let provider_types = data_sources
-            .values()
-            .map(|ds| ds.provider_type.clone())
-            .collect();
-        let wasm_modules = load_wasm_modules(wasm_dir, provider_types).await;
+        let (data_sources, wasm_modules) = load_data_sources(wasm_dir, data_sources).await;
 
         ProxyService::new(
             api_base,
             token,
+            wasm_dir,
             wasm_modules,
             data_sources,
             max_retries,
-            listen_address,
+            http_server,
             status_check_interval,
+            query_limits,
+        )
+    }
+
+    /// Load the providers of the given data sources from the wasm directory, to
+    /// query the data sources and check their status without connecting to
+    /// Fiberplane
+    pub async fn init_local(
+        wasm_dir: &Path,
+        data_sources: Vec<ProxyDataSource>,
+        query_limits: QueryLimits,
+    ) -> Self {
+        let (data_sources, wasm_modules) = load_data_sources(wasm_dir, data_sources).await;
+
+        ProxyService::local(wasm_dir, wasm_modules, data_sources, query_limits)
+    }
+
+    /// Create a service for the given data sources and their loaded providers,
+    /// which doesn't connect to Fiberplane
+    pub(crate) fn local(
+        wasm_dir: &Path,
+        wasm_modules: WasmModules,
+        data_sources: HashMap<Name, ProxyDataSource>,
+        query_limits: QueryLimits,
+    ) -> Self {
+        ProxyService::with_relay(
+            None,
+            wasm_dir,
+            wasm_modules,
+            data_sources,
+            0,
+            None,
+            Duration::from_secs(300),
+            query_limits,
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         api_base: Url,
         token: ProxyToken,
+        wasm_dir: &Path,
         wasm_modules: WasmModules,
         data_sources: HashMap<Name, ProxyDataSource>,
         max_retries: u32,
-        listen_address: Option<SocketAddr>,
+        http_server: Option<HttpServerConfig>,
         status_check_interval: Duration,
+        query_limits: QueryLimits,
     ) -> Self {
         let mut endpoint = api_base
             .join(&format!(
@@ -131,20 +441,201 @@ impl ProxyService {
                 .unwrap();
         }
 
-        ProxyService {
-            inner: Arc::new(Inner {
+        ProxyService::with_relay(
+            Some(Relay {
                 endpoint,
                 token: token.token,
-                data_sources,
+            }),
+            wasm_dir,
+            wasm_modules,
+            data_sources,
+            max_retries,
+            http_server,
+            status_check_interval,
+            query_limits,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn with_relay(
+        relay: Option<Relay>,
+        wasm_dir: &Path,
+        wasm_modules: WasmModules,
+        data_sources: HashMap<Name, ProxyDataSource>,
+        max_retries: u32,
+        http_server: Option<HttpServerConfig>,
+        status_check_interval: Duration,
+        query_limits: QueryLimits,
+    ) -> Self {
+        ProxyService {
+            inner: Arc::new(Inner {
+                relay,
+                data_sources: RwLock::new(data_sources),
                 data_sources_state: Default::default(),
-                wasm_modules,
+                next_status_checks: Default::default(),
+                data_sources_changed: Notify::new(),
+                wasm_dir: wasm_dir.to_path_buf(),
+                wasm_modules: RwLock::new(wasm_modules),
                 max_retries,
-                listen_address,
+                http_server,
                 status_check_interval,
+                global_query_slots: query_limits.max_concurrent_queries.map(|max_concurrent| {
+                    ConcurrencyLimit::new(max_concurrent, query_limits.max_queued_queries)
+                }),
+                query_slots: Default::default(),
+                blocking_pool: BlockingPool::new(query_limits.max_blocking_calls),
+                query_limits,
             }),
         }
     }
 
+    /// Replace the data sources served by this proxy.
+    ///
+    /// Providers that were not loaded yet (or failed to load previously) are
+    /// loaded from the wasm directory, and the status of the new set of data
+    /// sources is sent to the relay once checked. Queries that are in flight
+    /// keep using the data source and provider they started with.
+    #[instrument(skip_all)]
+    pub async fn reload_data_sources(&self, data_sources: Vec<ProxyDataSource>) {
+        let data_sources: HashMap<Name, ProxyDataSource> = data_sources
+            .into_iter()
+            .map(|data_source| (data_source.name.clone(), data_source))
+            .collect();
+        let modules: HashSet<PathBuf> = data_sources
+            .values()
+            .map(|ds| ds.provider_module(&self.inner.wasm_dir))
+            .collect();
+
+        let missing_modules = {
+            let wasm_modules = self.inner.wasm_modules.read().await;
+            modules
+                .iter()
+                .filter(|module| !matches!(wasm_modules.get(*module), Some(Ok(_))))
+                .cloned()
+                .collect()
+        };
+        let new_wasm_modules = load_wasm_modules(missing_modules).await;
+        {
+            let mut wasm_modules = self.inner.wasm_modules.write().await;
+            wasm_modules.extend(new_wasm_modules);
+            warn_about_undetected_protocol_versions(
+                &self.inner.wasm_dir,
+                data_sources.values(),
+                &wasm_modules,
+            );
+        }
+
+        // The states are updated while holding the lock on the data sources,
+        // so status checks of the previous data sources can't record theirs
+        let mut current_data_sources = self.inner.data_sources.write().await;
+        self.inner
+            .data_sources_state
+            .lock()
+            .await
+            .retain(|name, _| data_sources.contains_key(name));
+        self.inner
+            .query_slots
+            .lock()
+            .await
+            .retain(|name, _| data_sources.contains_key(name));
+        // Check the status of the new set of data sources right away
+        self.inner.next_status_checks.lock().await.clear();
+        *current_data_sources = data_sources;
+        drop(current_data_sources);
+
+        // Only drop the providers once no data source refers to them anymore
+        self.inner
+            .wasm_modules
+            .write()
+            .await
+            .retain(|module, _| modules.contains(module));
+
+        self.inner.data_sources_changed.notify_one();
+    }
+
+    /// Modules of the providers of the current data sources
+    pub(crate) async fn provider_modules(&self) -> HashSet<PathBuf> {
+        self.inner
+            .data_sources
+            .read()
+            .await
+            .values()
+            .map(|data_source| data_source.provider_module(&self.inner.wasm_dir))
+            .collect()
+    }
+
+    /// Recompile the providers of the given modules, after they changed on
+    /// disk.
+    ///
+    /// New queries use the new module right away, while queries in flight
+    /// finish on the previous one. A module that fails to compile doesn't
+    /// replace a working one. The status of the data sources using a reloaded
+    /// provider is checked again.
+    #[instrument(skip(self))]
+    pub async fn reload_providers(&self, modules: Vec<PathBuf>) {
+        // Providers that no data source uses are loaded on demand
+        let data_sources: Vec<ProxyDataSource> = {
+            let data_sources = self.inner.data_sources.read().await;
+            data_sources
+                .values()
+                .filter(|data_source| {
+                    modules.contains(&data_source.provider_module(&self.inner.wasm_dir))
+                })
+                .cloned()
+                .collect()
+        };
+        let modules: Vec<PathBuf> = modules
+            .into_iter()
+            .filter(|module| {
+                data_sources
+                    .iter()
+                    .any(|data_source| &data_source.provider_module(&self.inner.wasm_dir) == module)
+            })
+            .collect();
+        if modules.is_empty() {
+            return;
+        }
+
+        let new_wasm_modules = load_wasm_modules(modules).await;
+        let mut reloaded = HashSet::new();
+        {
+            let mut wasm_modules = self.inner.wasm_modules.write().await;
+            for (module, wasm_module) in new_wasm_modules {
+                match (&wasm_module, wasm_modules.get(&module)) {
+                    (Err(err), Some(Ok(_))) => {
+                        error!(module = %module.display(), "Keeping the current provider, the new module can't be loaded: {err:?}");
+                    }
+                    _ => {
+                        info!(module = %module.display(), "Reloaded provider");
+                        wasm_modules.insert(module.clone(), wasm_module);
+                        reloaded.insert(module);
+                    }
+                }
+            }
+            warn_about_undetected_protocol_versions(
+                &self.inner.wasm_dir,
+                data_sources.iter().filter(|data_source| {
+                    reloaded.contains(&data_source.provider_module(&self.inner.wasm_dir))
+                }),
+                &wasm_modules,
+            );
+        }
+        if reloaded.is_empty() {
+            return;
+        }
+
+        {
+            let data_sources = self.inner.data_sources.read().await;
+            let mut next_status_checks = self.inner.next_status_checks.lock().await;
+            for data_source in data_sources.values() {
+                if reloaded.contains(&data_source.provider_module(&self.inner.wasm_dir)) {
+                    next_status_checks.remove(&data_source.name);
+                }
+            }
+        }
+        self.inner.data_sources_changed.notify_one();
+    }
+
     /// Return a suitable ProxyMessage payload informing of the current
     /// state of all data sources.
     #[instrument(skip_all)]
@@ -174,7 +665,8 @@ impl ProxyService {
 
     #[instrument(err, skip_all)]
     pub async fn connect(&self, shutdown: Sender<()>) -> Result<()> {
-        info!("connecting to fiberplane: {}", self.inner.endpoint);
+        let relay = self.relay()?;
+        info!("connecting to fiberplane: {}", relay.endpoint);
         let (ws, mut conn_id_receiver) = self.connect_websocket().await?;
         conn_id_receiver.borrow_and_update();
 
@@ -204,10 +696,13 @@ impl ProxyService {
 
         // Health check endpoints
         let ws_clone = ws.clone();
-        if let Some(listen_address) = self.inner.listen_address {
+        if let Some(http_server) = self.inner.http_server.clone() {
+            let service = self.clone();
             tokio::spawn(
                 async move {
-                    if let Err(err) = serve_health_check_endpoints(listen_address, ws_clone).await {
+                    if let Err(err) =
+                        serve_health_check_endpoints(http_server, ws_clone, service).await
+                    {
                         // TODO should we shut the server down?
                         error!(?err, "Error serving health check endpoints");
                     }
@@ -224,16 +719,32 @@ impl ProxyService {
             unbounded_channel::<DataSourceCheckTask>();
         let data_source_check_task_tx_too = data_source_check_task_sender.clone();
         tokio::spawn(async move {
-            let mut status_check_interval = interval(service.inner.status_check_interval);
+            // Data sources can override the status check interval, so tick at the
+            // shortest one and only check the data sources that are due
+            let mut status_check_tick = service.status_check_tick().await;
+            let mut status_check_interval = interval(status_check_tick);
             loop {
                 select! {
                     // Note that the first tick returns immediately
                     _ = status_check_interval.tick().fuse() => {
-                        service.update_all_data_sources(data_source_check_task_sender.clone()).await;
                         // Update data sources will both try to connect and automatically queue individual retries to the
                         // `data_source_check_task_receiver` queue with the correct delay if necessary
+                        if service.update_all_data_sources(data_source_check_task_sender.clone(), status_check_tick / 2).await {
+                            let message = service.to_data_sources_proxy_message().await;
+                            debug!("sending data sources to relay: {:?}", message);
+                            data_sources_sender.send(message).ok();
+                        }
+                    }
+                    // The data sources were reloaded, check the new set right away
+                    _ = service.inner.data_sources_changed.notified().fuse() => {
+                        let tick = service.status_check_tick().await;
+                        if tick != status_check_tick {
+                            status_check_tick = tick;
+                            status_check_interval = interval_at(Instant::now() + tick, tick);
+                        }
+                        service.update_all_data_sources(data_source_check_task_sender.clone(), status_check_tick / 2).await;
                         let message = service.to_data_sources_proxy_message().await;
-                        debug!("sending data sources to relay: {:?}", message);
+                        debug!("sending reloaded data sources to relay: {:?}", message);
                         data_sources_sender.send(message).ok();
                     }
                     // A status check for a data source failed, and
@@ -259,15 +770,18 @@ impl ProxyService {
                     }
                     _ = shutdown_clone.recv().fuse() => {
                         // Let the relay know that all of these data sources are going offline
+                        let wasm_modules = service.inner.wasm_modules.read().await;
                         let data_sources = service
                             .inner
                             .data_sources
+                            .read()
+                            .await
                             .values()
                             .map(|data_source| UpsertProxyDataSource::builder()
                                 .name(data_source.name.clone())
                                 .description(data_source.description.clone())
                                 .provider_type(data_source.provider_type.clone())
-                                .protocol_version(get_protocol_version(&data_source.provider_type))
+                                .protocol_version(data_source.protocol_version(&service.inner.wasm_dir, &wasm_modules))
                                 .status(DataSourceStatus::Error(Error::ProxyDisconnected))
                             .build())
                             .collect();
@@ -365,6 +879,22 @@ impl ProxyService {
         Ok(())
     }
 
+    fn relay(&self) -> Result<&Relay> {
+        self.inner
+            .relay
+            .as_ref()
+            .ok_or_else(|| anyhow!("the service has no Fiberplane endpoint to connect to"))
+    }
+
+    /// The request to open the web-socket connection to the relay
+    fn relay_request(&self) -> Result<http::Request<()>> {
+        let relay = self.relay()?;
+        Ok(http::Request::builder()
+            .uri(relay.endpoint.as_str())
+            .header("fp-auth-token", relay.token.clone())
+            .body(())?)
+    }
+
     /// Connects to a web-socket server and returns the connection id and the
     /// web-socket stream.
     async fn connect_websocket(
@@ -372,10 +902,7 @@ impl ProxyService {
     ) -> Result<(ReconnectingWebSocket, watch::Receiver<Option<String>>)> {
         // Create a request object. If this fails there is no point in
         // retrying so just return the error object.
-        let request = http::Request::builder()
-            .uri(self.inner.endpoint.as_str())
-            .header("fp-auth-token", self.inner.token.clone())
-            .body(())?;
+        let request = self.relay_request()?;
 
         let (conn_id_sender, conn_id_receiver) = watch::channel(None);
         let ws = ReconnectingWebSocket::builder(request)?
@@ -400,6 +927,27 @@ impl ProxyService {
         }
     }
 
+    /// Open a connection to the relay once, without retrying, and close it
+    /// right away. Returns the connection id given by the relay.
+    pub(crate) async fn check_relay_connection(&self) -> Result<String> {
+        let request = self.relay_request()?;
+        let (mut ws, response) = tokio_tungstenite::connect_async(request).await?;
+        let conn_id = response
+            .headers()
+            .get("fp-conn-id")
+            .and_then(|id| id.to_str().map(|hv| hv.to_owned()).ok());
+        if let Err(err) = ws.close(None).await {
+            debug!(?err, "Error closing the connection to the relay");
+        }
+
+        conn_id.ok_or_else(|| anyhow!("no connection id was returned"))
+    }
+
+    /// The web-socket endpoint of the relay, if the service connects to one
+    pub(crate) fn relay_endpoint(&self) -> Option<&Url> {
+        self.inner.relay.as_ref().map(|relay| &relay.endpoint)
+    }
+
     #[instrument(skip_all, fields(
         trace_id = ?message.op_id,
         data_source_name = ?message.data_source_name,
@@ -418,19 +966,41 @@ impl ProxyService {
         Ok(())
     }
 
+    /// Handle a message from the relay (or the local API), waiting for the
+    /// concurrency limits of the queries
+    async fn handle_message_inner(&self, message: ServerMessage) -> Result<ProxyMessage> {
+        self.call_provider(message, true).await
+    }
+
+    /// Call the provider of the data source a message is for.
+    ///
+    /// Queries only wait for the concurrency limits when `limit_queries` is
+    /// set, so that status checks are never rejected or delayed because the
+    /// data source is busy.
     #[instrument(skip_all, fields(
         trace_id = ?message.op_id,
         data_source_name = ?message.data_source_name,
     ))]
-    async fn handle_message_inner(&self, message: ServerMessage) -> Result<ProxyMessage> {
+    async fn call_provider(
+        &self,
+        message: ServerMessage,
+        limit_queries: bool,
+    ) -> Result<ProxyMessage> {
         debug!("handling relay message");
         let op_id = message.op_id().ok_or_else(|| Error::Deserialization {
             message: "Incoming message is expecting an operation ID".to_string(),
         })?;
 
         // Try to create the runtime for the given data source
-        let data_source = match self.inner.data_sources.get(&message.data_source_name) {
-            Some(data_source) => data_source.clone(),
+        let data_source = self
+            .inner
+            .data_sources
+            .read()
+            .await
+            .get(&message.data_source_name)
+            .cloned();
+        let data_source = match data_source {
+            Some(data_source) => data_source,
             None => {
                 error!("received relay message for unknown data source");
 
@@ -438,15 +1008,21 @@ impl ProxyService {
             }
         };
 
-        let runtime = match self
+        let runtime = self
             .inner
             .wasm_modules
-            .get(&data_source.provider_type)
-            .unwrap()
-        {
-            Ok(runtime) => runtime,
-            Err(error) => {
-                return Ok(ProxyMessage::new_error_response(error.clone(), op_id));
+            .read()
+            .await
+            .get(&data_source.provider_module(&self.inner.wasm_dir))
+            .cloned();
+        let runtime = match runtime {
+            Some(Ok(module)) => module.runtime,
+            Some(Err(error)) => {
+                return Ok(ProxyMessage::new_error_response(error, op_id));
+            }
+            // The data sources were reloaded while handling this message
+            None => {
+                return Ok(ProxyMessage::new_error_response(Error::NotFound, op_id));
             }
         };
 
@@ -458,33 +1034,68 @@ impl ProxyService {
             &data_source.name,
         ];
         QUERIES_TOTAL.with_label_values(&labels).inc();
+
+        // Only the payloads that make the provider call the data source are
+        // subject to the concurrency limits
+        let _query_slots = match message.payload {
+            ServerMessagePayload::Invoke(_) | ServerMessagePayload::GetSupportedQueryTypes(_)
+                if limit_queries =>
+            {
+                match self.acquire_query_slots(&data_source, &labels).await {
+                    Ok(query_slots) => query_slots,
+                    Err(error) => return Ok(ProxyMessage::new_error_response(error, op_id)),
+                }
+            }
+            _ => Vec::new(),
+        };
+
         CONCURRENT_QUERIES.with_label_values(&labels).inc();
         let timer = QUERIES_DURATION_SECONDS
             .with_label_values(&labels)
             .start_timer();
 
+        let timeout = data_source.query_timeout(self.inner.query_limits.timeout);
+
         debug!(%protocol_version, %data_source.provider_type, %message.op_id, "Calling provider with {:?}", message.payload);
         let response = match (message.protocol_version, message.payload) {
             (1, ServerMessagePayload::Invoke(message)) => {
-                self.handle_invoke_proxy_message_v1(message, runtime, &data_source, op_id)
-                    .await
+                with_timeout(
+                    timeout,
+                    &labels,
+                    op_id,
+                    self.handle_invoke_proxy_message_v1(message, &runtime, &data_source, op_id),
+                )
+                .await
             }
             (2, ServerMessagePayload::Invoke(message)) => {
-                self.handle_invoke_proxy_message_v2(message, runtime, &data_source, op_id)
-                    .await
+                with_timeout(
+                    timeout,
+                    &labels,
+                    op_id,
+                    self.handle_invoke_proxy_message_v2(message, &runtime, &data_source, op_id),
+                )
+                .await
             }
             (2, ServerMessagePayload::CreateCells(message)) => {
-                self.handle_create_cells_proxy_message(message, runtime, &data_source, op_id)
+                self.handle_create_cells_proxy_message(message, &runtime, &data_source, op_id)
+                    .await
             }
             (2, ServerMessagePayload::ExtractData(message)) => {
-                self.handle_extract_data_proxy_message(message, runtime, &data_source, op_id)
+                self.handle_extract_data_proxy_message(message, &runtime, &data_source, op_id)
+                    .await
             }
             (2, ServerMessagePayload::GetConfigSchema(message)) => {
-                self.handle_config_schema_proxy_message(message, runtime, &data_source, op_id)
+                self.handle_config_schema_proxy_message(message, &runtime, &data_source, op_id)
+                    .await
             }
             (2, ServerMessagePayload::GetSupportedQueryTypes(message)) => {
-                self.handle_supported_query_types(message, runtime, &data_source, op_id)
-                    .await
+                with_timeout(
+                    timeout,
+                    &labels,
+                    op_id,
+                    self.handle_supported_query_types(message, &runtime, &data_source, op_id),
+                )
+                .await
             }
             (_, payload) => ProxyMessage::new_error_response(
                 Error::Invocation {
@@ -503,6 +1114,84 @@ impl ProxyService {
         Ok(response)
     }
 
+    /// Wait for a free query slot for the data source and a global one, if the
+    /// number of concurrent queries is limited.
+    ///
+    /// Fails with an overloaded error if too many queries are already waiting.
+    async fn acquire_query_slots(
+        &self,
+        data_source: &ProxyDataSource,
+        labels: &[&str],
+    ) -> Result<Vec<OwnedSemaphorePermit>, Error> {
+        let max_concurrent = data_source.max_concurrent_queries.or(self
+            .inner
+            .query_limits
+            .max_concurrent_queries_per_data_source);
+        let data_source_slots = match max_concurrent {
+            Some(max_concurrent) => {
+                let mut query_slots = self.inner.query_slots.lock().await;
+                let slots = query_slots
+                    .entry(data_source.name.clone())
+                    .or_insert_with(|| {
+                        Arc::new(ConcurrencyLimit::new(
+                            max_concurrent,
+                            self.inner.query_limits.max_queued_queries,
+                        ))
+                    });
+                // The limit changed since the data sources were reloaded
+                if slots.max_concurrent() != max_concurrent {
+                    *slots = Arc::new(ConcurrencyLimit::new(
+                        max_concurrent,
+                        self.inner.query_limits.max_queued_queries,
+                    ));
+                }
+                Some(slots.clone())
+            }
+            None => None,
+        };
+
+        let start = Instant::now();
+        QUEUED_QUERIES.with_label_values(labels).inc();
+        let mut permits = Vec::with_capacity(2);
+        let mut overloaded = None;
+        // Wait for the data source first, to not hold a global slot while the
+        // data source is busy
+        if let Some(slots) = &data_source_slots {
+            match slots.acquire().await {
+                Some(permit) => permits.push(permit),
+                None => {
+                    overloaded = Some(format!(
+                        "too many queries are waiting for data source '{}'",
+                        data_source.name
+                    ))
+                }
+            }
+        }
+        if let (None, Some(slots)) = (&overloaded, &self.inner.global_query_slots) {
+            match slots.acquire().await {
+                Some(permit) => permits.push(permit),
+                None => overloaded = Some("too many queries are waiting".to_string()),
+            }
+        }
+        QUEUED_QUERIES.with_label_values(labels).dec();
+
+        match overloaded {
+            Some(reason) => {
+                warn!("rejecting query, the daemon is overloaded: {reason}");
+                QUERIES_REJECTED_TOTAL.with_label_values(labels).inc();
+                Err(Error::Other {
+                    message: format!("The daemon is overloaded: {reason}, try again later"),
+                })
+            }
+            None => {
+                QUERIES_WAIT_SECONDS
+                    .with_label_values(labels)
+                    .observe(start.elapsed().as_secs_f64());
+                Ok(permits)
+            }
+        }
+    }
+
     #[instrument(skip_all, fields(
         trace_id = ?op_id,
         data_source_name = ?data_source.name,
@@ -545,15 +1234,28 @@ impl ProxyService {
         trace_id = ?op_id,
         data_source_name = ?data_source.name,
     ))]
-    fn handle_create_cells_proxy_message(
+    async fn handle_create_cells_proxy_message(
         &self,
         message: CreateCellsRequest,
-        runtime: &Runtime,
+        runtime: &Arc<Runtime>,
         data_source: &ProxyDataSource,
         op_id: Base64Uuid,
     ) -> ProxyMessage {
         info!("Calling create_cells on {}", data_source.name);
-        let result = bindings::create_cells(runtime, &message.query_type, message.response);
+        let runtime = runtime.clone();
+        let result = self
+            .inner
+            .blocking_pool
+            .run(
+                [
+                    "create_cells",
+                    &data_source.provider_type,
+                    &data_source.name,
+                ],
+                move || bindings::create_cells(&runtime, &message.query_type, message.response),
+            )
+            .await
+            .and_then(|result| result);
         match result {
             Ok(cells) => ProxyMessage::new_create_cells_response(cells, op_id),
             Err(error) => ProxyMessage::new_error_response(error, op_id),
@@ -564,20 +1266,35 @@ impl ProxyService {
         trace_id = ?op_id,
         data_source_name = ?data_source.name,
     ))]
-    fn handle_extract_data_proxy_message(
+    async fn handle_extract_data_proxy_message(
         &self,
         message: ExtractDataRequest,
-        runtime: &Runtime,
+        runtime: &Arc<Runtime>,
         data_source: &ProxyDataSource,
         op_id: Base64Uuid,
     ) -> ProxyMessage {
         info!("Calling extract_data on {}", data_source.name);
-        let result = bindings::extract_data(
-            runtime,
-            message.response,
-            &message.mime_type,
-            &message.query,
-        );
+        let runtime = runtime.clone();
+        let result = self
+            .inner
+            .blocking_pool
+            .run(
+                [
+                    "extract_data",
+                    &data_source.provider_type,
+                    &data_source.name,
+                ],
+                move || {
+                    bindings::extract_data(
+                        &runtime,
+                        message.response,
+                        &message.mime_type,
+                        &message.query,
+                    )
+                },
+            )
+            .await
+            .and_then(|result| result);
         match result {
             Ok(data) => ProxyMessage::new_extract_data_response(data, op_id),
             Err(error) => ProxyMessage::new_error_response(error, op_id),
@@ -588,15 +1305,28 @@ impl ProxyService {
         trace_id = ?op_id,
         data_source_name = ?data_source.name,
     ))]
-    fn handle_config_schema_proxy_message(
+    async fn handle_config_schema_proxy_message(
         &self,
         _message: GetConfigSchemaRequest,
-        runtime: &Runtime,
+        runtime: &Arc<Runtime>,
         data_source: &ProxyDataSource,
         op_id: Base64Uuid,
     ) -> ProxyMessage {
         info!("Calling get_config_schema on {}", data_source.name);
-        let result = bindings::get_config_schema(runtime);
+        let runtime = runtime.clone();
+        let result = self
+            .inner
+            .blocking_pool
+            .run(
+                [
+                    "get_config_schema",
+                    &data_source.provider_type,
+                    &data_source.name,
+                ],
+                move || bindings::get_config_schema(&runtime),
+            )
+            .await
+            .and_then(|result| result);
         match result {
             Ok(schema) => ProxyMessage::new_config_schema_response(schema, op_id),
             Err(error) => ProxyMessage::new_error_response(error, op_id),
@@ -633,46 +1363,85 @@ impl ProxyService {
         task: DataSourceCheckTask,
         individual_check_task_queue_tx: UnboundedSender<DataSourceCheckTask>,
     ) {
-        let update = self
+        let data_source = self
             .inner
             .data_sources
-            .iter()
-            .find(|(name, _)| *name == task.name())
-            .map(|(name, data_source)| async move {
-                let response = if V1_PROVIDERS.contains(&data_source.provider_type.as_str()) {
-                    self.check_provider_status_v1(name.clone()).await
-                } else {
-                    self.check_provider_status_v2(name.clone()).await
-                };
-
-                let status = match response {
-                    Ok(_) => DataSourceStatus::Connected,
-                    Err(ref err) => DataSourceStatus::Error(err.clone()),
-                };
-
-                if let Some((delay, task)) = task.next() {
-                    if response.is_err() {
-                        warn!(
-                            "error connecting to data source: {name}, retrying in {}s",
-                            delay.as_secs()
-                        );
-                        tokio::spawn(async move {
-                            tokio::time::sleep(delay).await;
-                            individual_check_task_queue_tx.send(task)
-                        });
-                    }
-                }
+            .read()
+            .await
+            .get(task.name())
+            .cloned();
+        let data_source = match data_source {
+            Some(data_source) => data_source,
+            None => {
+                debug!(
+                    "skipping status check of {}, it was removed from the data sources",
+                    task.name()
+                );
+                return;
+            }
+        };
+        let protocol_version = self.protocol_version(&data_source).await;
 
-                UpsertProxyDataSource::builder()
-                    .name(name.clone())
-                    .description(data_source.description.clone())
-                    .provider_type(data_source.provider_type.clone())
-                    .protocol_version(get_protocol_version(&data_source.provider_type))
-                    .status(status)
-                    .build()
-            })
-            .unwrap()
-            .await;
+        let response = if data_source.status_check_enabled() {
+            self.check_status(&data_source, protocol_version).await
+        } else {
+            self.check_provider_module(&data_source).await
+        };
+
+        self.record_status(
+            task,
+            individual_check_task_queue_tx,
+            &data_source,
+            protocol_version,
+            response,
+        )
+        .await;
+    }
+
+    /// Record the result of the status check of `data_source`, unless the
+    /// data source was changed or removed while it was checked
+    async fn record_status(
+        &self,
+        task: DataSourceCheckTask,
+        individual_check_task_queue_tx: UnboundedSender<DataSourceCheckTask>,
+        data_source: &ProxyDataSource,
+        protocol_version: u8,
+        response: Result<(), Error>,
+    ) {
+        let name = &data_source.name;
+        // Holding the lock until the status is recorded prevents a reload
+        // from happening in between
+        let data_sources = self.inner.data_sources.read().await;
+        if data_sources.get(name) != Some(data_source) {
+            debug!("discarding the status check of {name}, the data source changed while it was checked");
+            return;
+        }
+
+        let status = match response {
+            Ok(_) => DataSourceStatus::Connected,
+            Err(ref err) => DataSourceStatus::Error(err.clone()),
+        };
+
+        if let Some((delay, task)) = task.next() {
+            if response.is_err() {
+                warn!(
+                    "error connecting to data source: {name}, retrying in {}s",
+                    delay.as_secs()
+                );
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    individual_check_task_queue_tx.send(task)
+                });
+            }
+        }
+
+        let update = UpsertProxyDataSource::builder()
+            .name(name.clone())
+            .description(data_source.description.clone())
+            .provider_type(data_source.provider_type.clone())
+            .protocol_version(protocol_version)
+            .status(status)
+            .build();
 
         self.inner
             .data_sources_state
@@ -681,27 +1450,97 @@ impl ProxyService {
             .insert(update.name.clone(), update);
     }
 
+    /// Check the status of all the data sources that are due for a check
+    /// (within `tolerance`), and schedule their next check.
+    ///
+    /// Return whether any data source was checked.
     async fn update_all_data_sources(
         &self,
         to_check_task_queue: UnboundedSender<DataSourceCheckTask>,
-    ) {
+        tolerance: Duration,
+    ) -> bool {
+        let now = Instant::now();
+        let mut tasks = Vec::new();
+        {
+            let data_sources = self.inner.data_sources.read().await;
+            let mut next_status_checks = self.inner.next_status_checks.lock().await;
+            for data_source in data_sources.values() {
+                let due = next_status_checks
+                    .get(&data_source.name)
+                    .map_or(true, |next_check| *next_check <= now + tolerance);
+                if due {
+                    let interval =
+                        data_source.status_check_interval(self.inner.status_check_interval);
+                    next_status_checks.insert(data_source.name.clone(), now + interval);
+                    tasks.push(data_source.status_check_task(self.inner.status_check_interval));
+                }
+            }
+        }
+        if tasks.is_empty() {
+            return false;
+        }
+
         join_all(
-            self.inner
-                .data_sources
-                .iter()
+            tasks
+                .into_iter()
                 .zip(std::iter::repeat(to_check_task_queue))
-                .map(|((name, _), to_check_task_queue)| async move {
-                    let task = DataSourceCheckTask::new(
-                        name.clone(),
-                        self.inner.status_check_interval,
-                        Duration::from_secs(10),
-                        1.5,
-                    );
+                .map(|(task, to_check_task_queue)| async move {
                     self.update_data_source(task, to_check_task_queue.clone())
                         .await
                 }),
         )
         .await;
+        true
+    }
+
+    /// Return the interval at which to look for data sources that are due for
+    /// a status check, which is the shortest of their status check intervals
+    async fn status_check_tick(&self) -> Duration {
+        self.inner
+            .data_sources
+            .read()
+            .await
+            .values()
+            .filter(|data_source| data_source.status_check_enabled())
+            .map(|data_source| data_source.status_check_interval(self.inner.status_check_interval))
+            .fold(self.inner.status_check_interval, Duration::min)
+    }
+
+    /// Protocol version to use with the provider of a data source
+    pub(crate) async fn protocol_version(&self, data_source: &ProxyDataSource) -> u8 {
+        data_source.protocol_version(&self.inner.wasm_dir, &*self.inner.wasm_modules.read().await)
+    }
+
+    /// Check that the provider module of a data source is loaded, which is all
+    /// there is to check for the data sources that disable their status checks
+    pub(crate) async fn check_provider_module(
+        &self,
+        data_source: &ProxyDataSource,
+    ) -> Result<(), Error> {
+        let module = data_source.provider_module(&self.inner.wasm_dir);
+        match self.inner.wasm_modules.read().await.get(&module) {
+            Some(Ok(_)) => Ok(()),
+            Some(Err(error)) => Err(error.clone()),
+            None => Err(Error::Invocation {
+                message: format!("provider module {} isn't loaded", module.display()),
+            }),
+        }
+    }
+
+    /// Check the status of a data source once, with the given version of the
+    /// provider protocol
+    pub(crate) async fn check_status(
+        &self,
+        data_source: &ProxyDataSource,
+        protocol_version: u8,
+    ) -> Result<(), Error> {
+        if protocol_version == 1 {
+            self.check_provider_status_v1(data_source.name.clone())
+                .await
+        } else {
+            self.check_provider_status_v2(data_source.name.clone())
+                .await
+        }
     }
 
     #[instrument(err, skip(self))]
@@ -717,7 +1556,7 @@ impl ProxyService {
             Base64Uuid::new(),
         );
         let response = self
-            .handle_message_inner(message)
+            .call_provider(message, false)
             .await
             .expect("The handler only fails if the message has no op_id.");
         let response = match response.payload {
@@ -782,7 +1621,7 @@ impl ProxyService {
         );
 
         match self
-            .handle_message_inner(message)
+            .call_provider(message, false)
             .await
             .expect("The handler only fails if message has no op_id")
             .payload
@@ -805,44 +1644,132 @@ impl ProxyService {
     }
 }
 
-async fn load_wasm_modules(wasm_dir: &Path, provider_types: Vec<String>) -> WasmModules {
-    let runtimes = join_all(provider_types.iter().map(|data_source_type| async move {
-        // Each provider's wasm module is found in the wasm_dir as data_source_type.wasm
-        let wasm_path = &wasm_dir.join(format!("{}.wasm", &data_source_type));
+/// Wait for the response of a provider invocation, replacing it with a timeout
+/// error if it takes longer than `timeout`
+async fn with_timeout(
+    timeout: Option<Duration>,
+    labels: &[&str],
+    op_id: Base64Uuid,
+    response: impl Future<Output = ProxyMessage>,
+) -> ProxyMessage {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return response.await,
+    };
+    match tokio::time::timeout(timeout, response).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("provider did not respond within {timeout:?}, cancelling the query");
+            QUERIES_TIMEOUTS_TOTAL.with_label_values(labels).inc();
+            ProxyMessage::new_error_response(
+                Error::Http {
+                    error: HttpRequestError::Timeout,
+                },
+                op_id,
+            )
+        }
+    }
+}
+
+/// Index the data sources by name, and load the providers they use
+pub(crate) async fn load_data_sources(
+    wasm_dir: &Path,
+    data_sources: Vec<ProxyDataSource>,
+) -> (HashMap<Name, ProxyDataSource>, WasmModules) {
+    let data_sources: HashMap<Name, ProxyDataSource> = data_sources
+        .into_iter()
+        .map(|data_source| (data_source.name.clone(), data_source))
+        .collect();
+    let modules: HashSet<PathBuf> = data_sources
+        .values()
+        .map(|ds| ds.provider_module(wasm_dir))
+        .collect();
+    let wasm_modules = load_wasm_modules(modules.into_iter().collect()).await;
+    warn_about_undetected_protocol_versions(wasm_dir, data_sources.values(), &wasm_modules);
+    (data_sources, wasm_modules)
+}
+
+/// Warn about the data sources that use the default protocol version because
+/// it can't be detected from their provider module
+fn warn_about_undetected_protocol_versions<'a>(
+    wasm_dir: &Path,
+    data_sources: impl IntoIterator<Item = &'a ProxyDataSource>,
+    wasm_modules: &WasmModules,
+) {
+    for data_source in data_sources {
+        if data_source.has_undetected_protocol_version(wasm_dir, wasm_modules) {
+            warn!(
+                "Unable to detect the protocol version of the provider of data source {} ({}), using v{DEFAULT_PROTOCOL_VERSION}: set its protocolVersion to use another one",
+                data_source.name,
+                data_source.provider_module(wasm_dir).display()
+            );
+        }
+    }
+}
+
+/// Compile the given provider modules.
+///
+/// Providers are compiled again on every start: the provider runtime can only
+/// be created from the bytes of a wasm module, and doesn't expose the compiled
+/// module, so compiled artifacts can't be cached on disk and reused.
+pub(crate) async fn load_wasm_modules(modules: Vec<PathBuf>) -> WasmModules {
+    let runtimes = join_all(modules.iter().map(|wasm_path| async move {
         let wasm_module = fs::read(wasm_path).await.map_err(|err| {
             error!("Error reading wasm file: {} {}", wasm_path.display(), err);
             Error::Invocation {
                 message: format!("Error reading wasm file: {err}"),
             }
         })?;
-        Runtime::new(wasm_module).map_err(|err| {
+        let protocol_version = detect_protocol_version(&wasm_module);
+        let has_config_schema = exports_config_schema(&wasm_module);
+        let runtime = Runtime::new(wasm_module).map_err(|err| {
             error!("Error compiling wasm module: {}", err);
             Error::Invocation {
                 message: format!("Error compiling wasm module: {err}"),
             }
+        })?;
+        Ok(WasmModule {
+            runtime: Arc::new(runtime),
+            protocol_version,
+            has_config_schema,
         })
     }))
     .await;
 
-    provider_types.into_iter().zip(runtimes).collect()
-}
-fn get_protocol_version(provider_type: &str) -> u8 {
-    if V1_PROVIDERS.contains(&provider_type) {
-        1
-    } else {
-        2
-    }
+    modules.into_iter().zip(runtimes).collect()
 }
 
 /// Listen on the given address and return a 200 for GET /
-/// and either 200 or 502 for GET /health, depending on the WebSocket connection status
-async fn serve_health_check_endpoints(addr: SocketAddr, ws: ReconnectingWebSocket) -> Result<()> {
+/// and either 200 or 502 for GET /health, depending on the WebSocket connection status.
+/// Requests to /api/ are handled by the local API when it is enabled.
+async fn serve_health_check_endpoints(
+    config: HttpServerConfig,
+    ws: ReconnectingWebSocket,
+    service: ProxyService,
+) -> Result<()> {
+    let addr = config.listen_address;
+    let config = Arc::new(config);
     let make_svc = make_service_fn(move |_conn| {
         let ws = ws.clone();
+        let config = config.clone();
+        let service = service.clone();
         async move {
             Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
                 let ws = ws.clone();
+                let config = config.clone();
+                let service = service.clone();
                 async move {
+                    if config.local_api && request.uri().path().starts_with(local_api::PATH_PREFIX)
+                    {
+                        let method = request.method().clone();
+                        let path = request.uri().path().to_string();
+                        let response =
+                            local_api::handle(&service, config.local_api_token.as_deref(), request)
+                                .await;
+                        trace!(http_status_code = %response.status().as_u16(), http_method = %method, path = %path);
+                        return Ok::<_, Infallible>(response);
+                    }
+
                     let (status, body) = match (request.method(), request.uri().path()) {
                         (&Method::GET, "/") | (&Method::GET, "") => (
                             StatusCode::OK,
@@ -0,0 +1,63 @@
+use crate::tasks::metrics::{
+    BLOCKING_CALLS_DURATION_SECONDS, BLOCKING_CALLS_RUNNING, BLOCKING_CALLS_WAITING,
+};
+use fiberplane::models::providers::Error;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::error;
+
+/// A bounded pool of threads to run the synchronous calls into the providers
+/// (formatting cells, extracting data...), so a call on a big response can't
+/// stall the async executor that handles the relay connection.
+#[derive(Clone, Debug)]
+pub(crate) struct BlockingPool {
+    slots: Arc<Semaphore>,
+}
+
+impl BlockingPool {
+    /// A pool running up to `size` calls at the same time, which must be
+    /// greater than 0 (`--max-blocking-calls` rejects 0)
+    pub(crate) fn new(size: usize) -> Self {
+        assert!(size > 0, "the blocking pool needs at least one slot");
+        Self {
+            slots: Arc::new(Semaphore::new(size)),
+        }
+    }
+
+    /// Run `call` on a blocking thread once the pool has a free slot.
+    ///
+    /// `labels` are the function name, the provider type and the data source
+    /// name, used to track the calls in the metrics.
+    pub(crate) async fn run<T, F>(&self, labels: [&str; 3], call: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        BLOCKING_CALLS_WAITING.with_label_values(&labels).inc();
+        let slot = self.slots.clone().acquire_owned().await;
+        BLOCKING_CALLS_WAITING.with_label_values(&labels).dec();
+        let slot = slot.map_err(|_| Error::Other {
+            message: "The blocking pool is closed".to_string(),
+        })?;
+
+        BLOCKING_CALLS_RUNNING.with_label_values(&labels).inc();
+        let timer = BLOCKING_CALLS_DURATION_SECONDS
+            .with_label_values(&labels)
+            .start_timer();
+        let result = tokio::task::spawn_blocking(move || {
+            let result = call();
+            drop(slot);
+            result
+        })
+        .await;
+        timer.observe_duration();
+        BLOCKING_CALLS_RUNNING.with_label_values(&labels).dec();
+
+        result.map_err(|err| {
+            error!(?err, "provider call {} failed", labels[0]);
+            Error::Invocation {
+                message: format!("Error invoking provider: {err}"),
+            }
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A limit on the number of queries running at the same time, with a bounded
+/// number of queries waiting for a free slot.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    max_queued: usize,
+    queued: AtomicUsize,
+}
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// Take a slot, waiting for one to be released if they are all in use.
+    ///
+    /// Return `None` without waiting if `max_queued` queries are already
+    /// waiting for a slot.
+    pub(crate) async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        // Leave the queue even if the waiting future is dropped
+        let _queued = QueuedGuard(&self.queued);
+        self.semaphore.clone().acquire_owned().await.ok()
+    }
+}
+
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
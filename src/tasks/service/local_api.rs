@@ -0,0 +1,303 @@
+//! Local HTTP API to call the providers of the data sources without going
+//! through the relay.
+//!
+//! `POST /api/data-sources/{name}/{action}` builds the same message the relay
+//! would send for the action, and answers with the payload of the provider's
+//! response. Request and response bodies are JSON, or MessagePack when the
+//! request has a MessagePack `Content-Type`.
+
+use super::ProxyService;
+use fiberplane::base64uuid::Base64Uuid;
+use fiberplane::models::providers::Error;
+use fiberplane::models::proxies::*;
+use fiberplane::provider_bindings::Blob;
+use fiberplane::provider_runtime::spec::types::ProviderRequest;
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+use http::{Method, Request, Response, StatusCode};
+use hyper::body::{Bytes, HttpBody};
+use hyper::Body;
+use ring::constant_time::verify_slices_are_equal;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+#[cfg(test)]
+mod tests;
+
+/// Prefix of the paths served by the local API
+pub(crate) const PATH_PREFIX: &str = "/api/";
+
+const DATA_SOURCES_PREFIX: &str = "/api/data-sources/";
+
+/// Maximum size of request bodies, in bytes
+pub(crate) const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Encoding of the request and response bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    MessagePack,
+}
+
+impl Format {
+    /// The format of the request body, given by its `Content-Type` header
+    fn of(request: &Request<Body>) -> Self {
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "application/msgpack" | "application/x-msgpack" => Format::MessagePack,
+            _ => Format::Json,
+        }
+    }
+
+    fn mime_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Deserialize a request body, an empty body being an empty object
+    fn deserialize<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, String> {
+        if body.is_empty() {
+            return serde_json::from_slice(b"{}").map_err(|err| err.to_string());
+        }
+        match self {
+            Format::Json => serde_json::from_slice(body).map_err(|err| err.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(body).map_err(|err| err.to_string()),
+        }
+    }
+
+    fn respond<T: Serialize>(self, status: StatusCode, body: &T) -> Response<Body> {
+        let body = match self {
+            Format::Json => serde_json::to_vec(body).map_err(|err| err.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(body).map_err(|err| err.to_string()),
+        };
+        match body {
+            Ok(body) => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, self.mime_type())
+                .body(Body::from(body))
+                .unwrap(),
+            Err(err) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(format!("Error serializing the response: {err}")))
+                .unwrap(),
+        }
+    }
+
+    fn error(self, status: StatusCode, message: impl Into<String>) -> Response<Body> {
+        self.respond(
+            status,
+            &ErrorBody {
+                error: message.into(),
+            },
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<T> {
+    error: T,
+}
+
+/// Handle a request to the local API.
+///
+/// When `token` is set, requests must have an `Authorization: Bearer <token>`
+/// header. Request bodies larger than [MAX_BODY_SIZE] are refused.
+pub(crate) async fn handle(
+    service: &ProxyService,
+    token: Option<&str>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let format = Format::of(&request);
+
+    if let Some(token) = token {
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let expected = format!("Bearer {token}");
+        let authorized = authorization.map_or(false, |authorization| {
+            verify_slices_are_equal(authorization.as_bytes(), expected.as_bytes()).is_ok()
+        });
+        if !authorized {
+            return format.error(StatusCode::UNAUTHORIZED, "missing or invalid token");
+        }
+    }
+
+    let path = request.uri().path().to_string();
+    let (name, action) = match path
+        .strip_prefix(DATA_SOURCES_PREFIX)
+        .and_then(|path| path.split_once('/'))
+    {
+        Some(route) => route,
+        None => return format.error(StatusCode::NOT_FOUND, "not found"),
+    };
+    if !matches!(
+        action,
+        "invoke" | "create-cells" | "extract-data" | "config-schema" | "query-types"
+    ) {
+        return format.error(StatusCode::NOT_FOUND, "not found");
+    }
+    if request.method() != Method::POST {
+        return format.error(StatusCode::METHOD_NOT_ALLOWED, "only POST is allowed");
+    }
+
+    let data_source = service
+        .inner
+        .data_sources
+        .read()
+        .await
+        .values()
+        .find(|data_source| data_source.name.to_string() == name)
+        .cloned();
+    let data_source = match data_source {
+        Some(data_source) => data_source,
+        None => {
+            return format.error(
+                StatusCode::NOT_FOUND,
+                format!("unknown data source '{name}'"),
+            )
+        }
+    };
+    let protocol_version = service.protocol_version(&data_source).await;
+    if protocol_version != 2 {
+        return format.error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "the '{}' provider uses protocol v{protocol_version}, which isn't supported by the local API",
+                data_source.provider_type
+            ),
+        );
+    }
+
+    let body = match read_body(request.into_body(), MAX_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(BodyError::TooLarge) => {
+            return format.error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("the request body is larger than {MAX_BODY_SIZE} bytes"),
+            )
+        }
+        Err(BodyError::Read(err)) => {
+            return format.error(
+                StatusCode::BAD_REQUEST,
+                format!("unable to read the request body: {err}"),
+            )
+        }
+    };
+    let payload = match action {
+        "invoke" => format
+            .deserialize::<ProviderRequest>(&body)
+            .and_then(|request| rmp_serde::to_vec_named(&request).map_err(|err| err.to_string()))
+            .map(|data| ServerMessagePayload::Invoke(InvokeRequest { data })),
+        "create-cells" => format
+            .deserialize(&body)
+            .map(ServerMessagePayload::CreateCells),
+        "extract-data" => format
+            .deserialize(&body)
+            .map(ServerMessagePayload::ExtractData),
+        "config-schema" => format
+            .deserialize(&body)
+            .map(ServerMessagePayload::GetConfigSchema),
+        _ => format
+            .deserialize(&body)
+            .map(ServerMessagePayload::GetSupportedQueryTypes),
+    };
+    let payload = match payload {
+        Ok(payload) => payload,
+        Err(err) => {
+            return format.error(
+                StatusCode::BAD_REQUEST,
+                format!("invalid request body: {err}"),
+            )
+        }
+    };
+
+    debug!(data_source = %data_source.name, %action, "Handling local API request");
+    let message = ServerMessage {
+        op_id: Base64Uuid::new(),
+        data_source_name: data_source.name.clone(),
+        protocol_version,
+        payload,
+    };
+    match service.handle_message_inner(message).await {
+        Ok(response) => respond_with_payload(format, response.payload),
+        Err(err) => format.error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+enum BodyError {
+    TooLarge,
+    Read(hyper::Error),
+}
+
+/// Read a request body, giving up as soon as it's larger than `limit` bytes
+async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, BodyError> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(BodyError::TooLarge);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(BodyError::Read)?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(BodyError::TooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+/// Turn the response to a relay message into an HTTP response
+fn respond_with_payload(format: Format, payload: ProxyMessagePayload) -> Response<Body> {
+    match payload {
+        ProxyMessagePayload::InvokeProxyResponse(response) => {
+            match rmp_serde::from_slice::<Result<Blob, Error>>(&response.data) {
+                Ok(Ok(blob)) => format.respond(StatusCode::OK, &blob),
+                Ok(Err(error)) => provider_error(format, error),
+                Err(err) => format.error(
+                    StatusCode::BAD_GATEWAY,
+                    format!("unable to deserialize the provider response: {err}"),
+                ),
+            }
+        }
+        ProxyMessagePayload::CreateCellsResponse(response) => match response.cells {
+            Ok(cells) => format.respond(StatusCode::OK, &cells),
+            Err(error) => provider_error(format, error),
+        },
+        ProxyMessagePayload::ExtractDataResponse(response) => match response.data {
+            Ok(blob) => format.respond(StatusCode::OK, &blob),
+            Err(error) => provider_error(format, error),
+        },
+        ProxyMessagePayload::ConfigSchemaResponse(response) => {
+            format.respond(StatusCode::OK, &response.schema)
+        }
+        ProxyMessagePayload::SupportedQueryTypesResponse(response) => {
+            format.respond(StatusCode::OK, &response.queries)
+        }
+        ProxyMessagePayload::Error(message) => provider_error(format, message.error),
+        payload => format.error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unexpected response: {payload:?}"),
+        ),
+    }
+}
+
+/// Errors of the provider (or of the data source it calls) are answered with
+/// a 502, except for data sources that disappeared while handling the request
+fn provider_error(format: Format, error: Error) -> Response<Body> {
+    let status = match error {
+        Error::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    format.respond(status, &ErrorBody { error })
+}
@@ -0,0 +1,247 @@
+use super::{handle, respond_with_payload, Format, MAX_BODY_SIZE};
+use crate::tasks::service::{ProxyDataSource, ProxyService};
+use fiberplane::base64uuid::Base64Uuid;
+use fiberplane::models::names::Name;
+use fiberplane::models::providers::Error;
+use fiberplane::models::proxies::ProxyMessage;
+use http::header::{AUTHORIZATION, CONTENT_TYPE};
+use http::{Method, Request, Response, StatusCode};
+use hyper::Body;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+use tempfile::TempDir;
+
+async fn service(wasm_dir: &Path) -> ProxyService {
+    let data_sources = vec![ProxyDataSource::new(
+        Name::from_static("prometheus-dev"),
+        "prometheus".to_string(),
+        Map::new(),
+    )];
+    ProxyService::init_local(wasm_dir, data_sources, Default::default()).await
+}
+
+fn request(method: Method, path: &str) -> http::request::Builder {
+    Request::builder().method(method).uri(path)
+}
+
+async fn json_body(response: Response<Body>) -> Value {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn routes_requests_to_the_data_sources() {
+    let dir = TempDir::new().unwrap();
+    let service = service(dir.path()).await;
+
+    let response = handle(
+        &service,
+        None,
+        request(Method::POST, "/api/unknown")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = handle(
+        &service,
+        None,
+        request(Method::POST, "/api/data-sources/prometheus-dev/delete")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = handle(
+        &service,
+        None,
+        request(
+            Method::GET,
+            "/api/data-sources/prometheus-dev/config-schema",
+        )
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let response = handle(
+        &service,
+        None,
+        request(
+            Method::POST,
+            "/api/data-sources/prometheus-prod/config-schema",
+        )
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        json_body(response).await,
+        json!({ "error": "unknown data source 'prometheus-prod'" })
+    );
+
+    let response = handle(
+        &service,
+        None,
+        request(
+            Method::POST,
+            "/api/data-sources/prometheus-dev/create-cells",
+        )
+        .body(Body::from("not json"))
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // The provider isn't installed, so the request reaches the handlers of
+    // the relay messages, which answer with an error
+    let response = handle(
+        &service,
+        None,
+        request(
+            Method::POST,
+            "/api/data-sources/prometheus-dev/config-schema",
+        )
+        .body(Body::empty())
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/json"
+    );
+    assert!(json_body(response).await["error"].is_object());
+}
+
+#[tokio::test]
+async fn requires_the_token() {
+    let dir = TempDir::new().unwrap();
+    let service = service(dir.path()).await;
+    let path = "/api/data-sources/prometheus-prod/query-types";
+
+    let response = handle(
+        &service,
+        Some("secret"),
+        request(Method::POST, path).body(Body::empty()).unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = handle(
+        &service,
+        Some("secret"),
+        request(Method::POST, path)
+            .header(AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = handle(
+        &service,
+        Some("secret"),
+        request(Method::POST, path)
+            .header(AUTHORIZATION, "Bearer secret2")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = handle(
+        &service,
+        Some("secret"),
+        request(Method::POST, path)
+            .header(AUTHORIZATION, "Bearer secret")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn refuses_large_bodies() {
+    let dir = TempDir::new().unwrap();
+    let service = service(dir.path()).await;
+    let path = "/api/data-sources/prometheus-dev/invoke";
+
+    let response = handle(
+        &service,
+        None,
+        request(Method::POST, path)
+            .body(Body::from(vec![b' '; MAX_BODY_SIZE + 1]))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Without a length known upfront, the body is read until it's too large
+    let chunks = (0..3).map(|_| Ok::<_, std::io::Error>(vec![b' '; MAX_BODY_SIZE / 2]));
+    let response = handle(
+        &service,
+        None,
+        request(Method::POST, path)
+            .body(Body::wrap_stream(futures::stream::iter(chunks)))
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        json_body(response).await,
+        json!({ "error": format!("the request body is larger than {MAX_BODY_SIZE} bytes") })
+    );
+}
+
+#[tokio::test]
+async fn answers_in_the_format_of_the_request() {
+    let dir = TempDir::new().unwrap();
+    let service = service(dir.path()).await;
+
+    let response = handle(
+        &service,
+        None,
+        request(Method::POST, "/api/data-sources/prometheus-prod/invoke")
+            .header(CONTENT_TYPE, "application/msgpack")
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/msgpack"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": "unknown data source 'prometheus-prod'" })
+    );
+}
+
+#[tokio::test]
+async fn maps_provider_errors_to_statuses() {
+    let op_id = Base64Uuid::new();
+
+    let payload = ProxyMessage::new_error_response(Error::NotFound, op_id).payload;
+    let response = respond_with_payload(Format::Json, payload);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let error = Error::Other {
+        message: "connection refused".to_string(),
+    };
+    let payload = ProxyMessage::new_error_response(error.clone(), op_id).payload;
+    let response = respond_with_payload(Format::Json, payload);
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        json_body(response).await,
+        json!({ "error": serde_json::to_value(&error).unwrap() })
+    );
+}
@@ -0,0 +1,58 @@
+//! Detection of the protocol version, and of the optional functions,
+//! implemented by a provider module
+
+use wasmparser::{ExternalKind, Parser, Payload};
+
+/// Protocol version used when a data source doesn't set one and it can't be
+/// detected from its provider
+pub(crate) const DEFAULT_PROTOCOL_VERSION: u8 = 2;
+
+/// Functions exported by the providers of each protocol version
+const ENTRY_POINTS: &[(&str, u8)] = &[("__fp_gen_invoke2", 2), ("__fp_gen_invoke", 1)];
+
+/// Function exported by the providers that publish a config schema
+const CONFIG_SCHEMA_ENTRY_POINT: &str = "__fp_gen_get_config_schema";
+
+/// Detect the protocol version of a provider from the functions exported by
+/// its module.
+///
+/// Modules that implement several versions use the newest one. Returns `None`
+/// if the module exports none of the known entry points, or can't be parsed.
+pub(crate) fn detect_protocol_version(wasm_module: &[u8]) -> Option<u8> {
+    exported_functions(wasm_module)?
+        .into_iter()
+        .filter_map(|function| {
+            ENTRY_POINTS
+                .iter()
+                .find(|(entry_point, _)| *entry_point == function)
+                .map(|(_, version)| *version)
+        })
+        .max()
+}
+
+/// Whether a provider module exports the function returning its config
+/// schema, which older providers don't
+pub(crate) fn exports_config_schema(wasm_module: &[u8]) -> bool {
+    exported_functions(wasm_module).map_or(false, |functions| {
+        functions.contains(&CONFIG_SCHEMA_ENTRY_POINT)
+    })
+}
+
+/// Names of the functions exported by a module, or `None` if it can't be
+/// parsed
+fn exported_functions(wasm_module: &[u8]) -> Option<Vec<&str>> {
+    let mut functions = Vec::new();
+    for payload in Parser::new(0).parse_all(wasm_module) {
+        let exports = match payload.ok()? {
+            Payload::ExportSection(exports) => exports,
+            _ => continue,
+        };
+        for export in exports {
+            let export = export.ok()?;
+            if matches!(export.kind, ExternalKind::Function) {
+                functions.push(export.field);
+            }
+        }
+    }
+    Some(functions)
+}
@@ -1,6 +1,78 @@
+use crate::interval::IntervalDuration;
 use fiberplane::models::names::Name;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::time::Duration;
 
+/// Delay before retrying a failed status check, unless overridden
+pub(crate) const DEFAULT_INITIAL_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Growth of the delay between successive retries, unless overridden
+pub(crate) const DEFAULT_BACKOFF_FACTOR: f32 = 1.5;
+
+/// Overrides of the status check policy for a single data source
+///
+/// ```yaml
+/// statusCheck:
+///   interval: 5m
+///   initialRetryDelay: 30s
+///   backoffFactor: 2
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct StatusCheckConfig {
+    /// Set to false to never check the status of the data source, which is
+    /// then reported as connected as long as its provider module is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Interval between two status checks, instead of the global
+    /// `--status-check-interval`
+    #[serde(
+        default,
+        deserialize_with = "deserialize_non_zero_interval",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub interval: Option<IntervalDuration>,
+    /// Delay before the first retry of a failed status check
+    #[serde(
+        default,
+        deserialize_with = "deserialize_non_zero_interval",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub initial_retry_delay: Option<IntervalDuration>,
+    /// Factor by which the delay grows between retries
+    #[serde(
+        default,
+        deserialize_with = "deserialize_backoff_factor",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub backoff_factor: Option<f32>,
+}
+
+fn deserialize_non_zero_interval<'de, D>(
+    deserializer: D,
+) -> Result<Option<IntervalDuration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match IntervalDuration::deserialize(deserializer)? {
+        IntervalDuration(duration) if duration.is_zero() => {
+            Err(de::Error::custom("the interval must be greater than 0s"))
+        }
+        interval => Ok(Some(interval)),
+    }
+}
+
+fn deserialize_backoff_factor<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match f32::deserialize(deserializer)? {
+        factor if factor > 1.0 && factor.is_finite() => Ok(Some(factor)),
+        factor => Err(de::Error::custom(format!(
+            "the backoff factor must be greater than 1, found {factor}"
+        ))),
+    }
+}
+
 /// A token representing both:
 /// - a task to check the status of the data source having a given name, and
 /// - the retry strategy to use in case the status check failed.
@@ -1,6 +1,19 @@
-use super::status_check::DataSourceCheckTask;
-use fiberplane::models::names::Name;
-use std::time::Duration;
+use super::blocking_pool::BlockingPool;
+use super::concurrency::ConcurrencyLimit;
+use super::status_check::{DataSourceCheckTask, StatusCheckConfig};
+use super::{
+    detect_protocol_version, exports_config_schema, with_timeout, HttpServerConfig,
+    ProxyDataSource, ProxyService, QueryLimits, WasmModules, STATUS_REQUEST_V2,
+};
+use crate::interval::IntervalDuration;
+use crate::tasks::metrics::QUERIES_TIMEOUTS_TOTAL;
+use fiberplane::base64uuid::Base64Uuid;
+use fiberplane::models::providers::{Error, HttpRequestError};
+use fiberplane::models::{data_sources::DataSourceStatus, names::Name, proxies::*};
+use serde_json::Map;
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time::{advance, Instant};
 
 #[test]
 fn exponential_backoff_cap() {
@@ -36,3 +49,514 @@ fn exponential_backoff_cap() {
     test_case(Duration::from_secs(300), Duration::from_secs(1000), 1.5);
     test_case(Duration::from_secs(300), Duration::from_secs(300), 1.5);
 }
+
+fn unchecked_data_source(name: &'static str, interval: Option<u64>) -> ProxyDataSource {
+    ProxyDataSource {
+        status_check: Some(StatusCheckConfig {
+            enabled: Some(false),
+            interval: interval.map(|secs| IntervalDuration(Duration::from_secs(secs))),
+            ..Default::default()
+        }),
+        ..ProxyDataSource::new(
+            Name::from_static(name),
+            "prometheus".to_string(),
+            Map::new(),
+        )
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn checks_data_sources_at_their_own_interval() {
+    let data_sources: HashMap<Name, ProxyDataSource> = vec![
+        unchecked_data_source("fast", Some(10)),
+        unchecked_data_source("slow", Some(60)),
+        unchecked_data_source("default", None),
+    ]
+    .into_iter()
+    .map(|data_source| (data_source.name.clone(), data_source))
+    .collect();
+    let service = ProxyService::new(
+        "http://127.0.0.1:3000".parse().unwrap(),
+        ProxyToken::builder()
+            .workspace_id(Base64Uuid::new())
+            .proxy_name(Name::from_static("test-proxy"))
+            .token("MVPpfxAYRxcQ4rFZUB7RRzirzwhR7htlkU3zcDm-pZk")
+            .build(),
+        Path::new("./providers"),
+        Default::default(),
+        data_sources,
+        5,
+        None,
+        Duration::from_secs(300),
+        Default::default(),
+    );
+    let (sender, _receiver) = unbounded_channel();
+
+    // Unchecked data sources don't make the checks more frequent
+    assert_eq!(service.status_check_tick().await, Duration::from_secs(300));
+
+    let start = Instant::now();
+    assert!(
+        service
+            .update_all_data_sources(sender.clone(), Duration::from_secs(5))
+            .await
+    );
+    advance(Duration::from_secs(10)).await;
+    assert!(
+        service
+            .update_all_data_sources(sender.clone(), Duration::from_secs(5))
+            .await
+    );
+    advance(Duration::from_secs(1)).await;
+    assert!(
+        !service
+            .update_all_data_sources(sender.clone(), Duration::from_secs(5))
+            .await
+    );
+
+    let next_status_checks = service.inner.next_status_checks.lock().await.clone();
+    assert_eq!(
+        next_status_checks[&Name::from_static("fast")],
+        start + Duration::from_secs(20)
+    );
+    assert_eq!(
+        next_status_checks[&Name::from_static("slow")],
+        start + Duration::from_secs(60)
+    );
+    assert_eq!(
+        next_status_checks[&Name::from_static("default")],
+        start + Duration::from_secs(300)
+    );
+
+    // Data sources with disabled checks are still reported as failing when
+    // their provider isn't loaded
+    for name in &["fast", "slow", "default"] {
+        let state = service
+            .data_source_state(&Name::from_static(name))
+            .await
+            .unwrap();
+        assert!(matches!(
+            state.status,
+            DataSourceStatus::Error(Error::Invocation { .. })
+        ));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn discards_status_checks_of_reloaded_data_sources() {
+    let data_source = unchecked_data_source("metrics", None);
+    let service = ProxyService::init_local(
+        Path::new("./providers"),
+        vec![data_source.clone()],
+        Default::default(),
+    )
+    .await;
+    let (sender, _receiver) = unbounded_channel();
+
+    // A check of the data source that takes 10s to complete
+    let check = |data_source: ProxyDataSource| {
+        let service = service.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let task = data_source.status_check_task(Duration::from_secs(300));
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            service
+                .record_status(task, sender, &data_source, 2, Ok(()))
+                .await;
+        })
+    };
+    let name = Name::from_static("metrics");
+
+    // The data source changes while it is checked
+    let in_flight = check(data_source.clone());
+    advance(Duration::from_secs(1)).await;
+    let mut changed = data_source.clone();
+    changed.description = Some("Changed".to_string());
+    service.reload_data_sources(vec![changed.clone()]).await;
+    advance(Duration::from_secs(10)).await;
+    in_flight.await.unwrap();
+    assert!(service.data_source_state(&name).await.is_err());
+
+    // The data source is removed while it is checked
+    let in_flight = check(changed.clone());
+    advance(Duration::from_secs(1)).await;
+    service.reload_data_sources(Vec::new()).await;
+    advance(Duration::from_secs(10)).await;
+    in_flight.await.unwrap();
+    assert!(service.data_source_state(&name).await.is_err());
+
+    // Checks of the current data sources are recorded
+    service.reload_data_sources(vec![changed.clone()]).await;
+    let in_flight = check(changed);
+    advance(Duration::from_secs(11)).await;
+    in_flight.await.unwrap();
+    assert_eq!(
+        service.data_source_state(&name).await.unwrap().status,
+        DataSourceStatus::Connected
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn times_out_slow_invocations() {
+    let mut data_source = unchecked_data_source("slow-prometheus", None);
+    assert_eq!(
+        data_source.query_timeout(Some(Duration::from_secs(120))),
+        Some(Duration::from_secs(120))
+    );
+    data_source.query_timeout = Some(IntervalDuration(Duration::from_secs(0)));
+    assert_eq!(
+        data_source.query_timeout(Some(Duration::from_secs(120))),
+        None
+    );
+    data_source.query_timeout = Some(IntervalDuration(Duration::from_secs(10)));
+    let timeout = data_source.query_timeout(Some(Duration::from_secs(120)));
+    assert_eq!(timeout, Some(Duration::from_secs(10)));
+
+    let labels = ["2", "prometheus", "slow-prometheus"];
+    let op_id = Base64Uuid::new();
+    let response = with_timeout(timeout, &labels, op_id, async {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        ProxyMessage::new_error_response(Error::NotFound, op_id)
+    })
+    .await;
+    assert!(matches!(
+        response.payload,
+        ProxyMessagePayload::Error(ErrorMessage {
+            error: Error::NotFound,
+            ..
+        })
+    ));
+    assert_eq!(QUERIES_TIMEOUTS_TOTAL.with_label_values(&labels).get(), 0);
+
+    let response = with_timeout(
+        timeout,
+        &labels,
+        op_id,
+        futures::future::pending::<ProxyMessage>(),
+    )
+    .await;
+    assert_eq!(response.op_id(), Some(op_id));
+    assert!(matches!(
+        response.payload,
+        ProxyMessagePayload::Error(ErrorMessage {
+            error: Error::Http {
+                error: HttpRequestError::Timeout
+            },
+            ..
+        })
+    ));
+    assert_eq!(QUERIES_TIMEOUTS_TOTAL.with_label_values(&labels).get(), 1);
+}
+
+#[tokio::test]
+async fn rejects_queries_when_the_queue_is_full() {
+    let limit = Arc::new(ConcurrencyLimit::new(1, 1));
+    let running = limit.acquire().await.expect("a slot is free");
+
+    let queued = tokio::spawn({
+        let limit = limit.clone();
+        async move { limit.acquire().await.is_some() }
+    });
+    // Let the second query join the queue
+    tokio::task::yield_now().await;
+
+    assert!(
+        limit.acquire().await.is_none(),
+        "the queue already holds one query"
+    );
+
+    drop(running);
+    assert!(queued.await.unwrap(), "the queued query gets the slot");
+}
+
+#[tokio::test]
+async fn runs_blocking_calls_in_the_pool() {
+    let pool = BlockingPool::new(1);
+    let labels = ["create_cells", "prometheus", "prometheus-dev"];
+
+    let results = futures::future::join_all((0..4).map(|index| {
+        pool.run(labels, move || {
+            std::thread::sleep(Duration::from_millis(10));
+            index * 2
+        })
+    }))
+    .await;
+    assert_eq!(
+        results.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+        vec![0, 2, 4, 6]
+    );
+
+    let result = pool
+        .run(labels, || -> usize { panic!("the provider trapped") })
+        .await;
+    assert!(matches!(result, Err(Error::Invocation { .. })));
+
+    // The slot of the failed call is released
+    assert_eq!(pool.run(labels, || 42).await.unwrap(), 42);
+}
+
+#[tokio::test]
+async fn reloads_the_providers_of_changed_modules() {
+    let wasm_dir = tempfile::TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    std::fs::write(wasm_dir.join("prometheus.wasm"), "not a wasm module").unwrap();
+
+    let mut loki = unchecked_data_source("logs", None);
+    loki.provider_type = "loki".to_string();
+    let data_sources: HashMap<Name, ProxyDataSource> =
+        vec![unchecked_data_source("metrics", None), loki]
+            .into_iter()
+            .map(|data_source| (data_source.name.clone(), data_source))
+            .collect();
+    let not_loaded = || {
+        Err(Error::Invocation {
+            message: "not loaded".to_string(),
+        })
+    };
+    let wasm_modules = vec![
+        (wasm_dir.join("prometheus.wasm"), not_loaded()),
+        (wasm_dir.join("loki.wasm"), not_loaded()),
+    ]
+    .into_iter()
+    .collect();
+    let service = ProxyService::new(
+        "http://127.0.0.1:3000".parse().unwrap(),
+        ProxyToken::builder()
+            .workspace_id(Base64Uuid::new())
+            .proxy_name(Name::from_static("test-proxy"))
+            .token("MVPpfxAYRxcQ4rFZUB7RRzirzwhR7htlkU3zcDm-pZk")
+            .build(),
+        wasm_dir,
+        wasm_modules,
+        data_sources,
+        5,
+        None,
+        Duration::from_secs(300),
+        Default::default(),
+    );
+    let (sender, _receiver) = unbounded_channel();
+    service
+        .update_all_data_sources(sender, Duration::ZERO)
+        .await;
+
+    // Providers that no data source uses are ignored
+    service
+        .reload_providers(vec![
+            wasm_dir.join("prometheus.wasm"),
+            wasm_dir.join("sentry.wasm"),
+        ])
+        .await;
+
+    let wasm_modules = service.inner.wasm_modules.read().await;
+    match &wasm_modules[&wasm_dir.join("prometheus.wasm")] {
+        Err(Error::Invocation { message }) => {
+            assert!(message.starts_with("Error compiling wasm module"))
+        }
+        _ => panic!("the module can't be compiled"),
+    }
+    assert!(!wasm_modules.contains_key(&wasm_dir.join("sentry.wasm")));
+
+    // Only the data sources using the reloaded provider are checked again
+    let next_status_checks = service.inner.next_status_checks.lock().await;
+    assert!(!next_status_checks.contains_key(&Name::from_static("metrics")));
+    assert!(next_status_checks.contains_key(&Name::from_static("logs")));
+}
+
+#[tokio::test]
+async fn swaps_in_new_modules_and_keeps_working_ones() {
+    let providers = Path::new(env!("CARGO_MANIFEST_DIR")).join("providers");
+    let wasm_dir = tempfile::TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    let module = wasm_dir.join("prometheus.wasm");
+    std::fs::copy(providers.join("prometheus.wasm"), &module).unwrap();
+    let service = ProxyService::init_local(
+        wasm_dir,
+        vec![unchecked_data_source("metrics", None)],
+        Default::default(),
+    )
+    .await;
+    let loaded_runtime = |wasm_modules: &WasmModules| match &wasm_modules[&module] {
+        Ok(wasm_module) => (wasm_module.runtime.clone(), wasm_module.protocol_version),
+        Err(err) => panic!("the module isn't loaded: {:?}", err),
+    };
+    let (runtime, protocol_version) = loaded_runtime(&*service.inner.wasm_modules.read().await);
+    assert_eq!(protocol_version, Some(2));
+
+    // A valid new module replaces the loaded one, while the calls in flight
+    // keep the previous runtime
+    std::fs::copy(providers.join("loki.wasm"), &module).unwrap();
+    service.reload_providers(vec![module.clone()]).await;
+    let (new_runtime, protocol_version) = loaded_runtime(&*service.inner.wasm_modules.read().await);
+    assert!(!Arc::ptr_eq(&runtime, &new_runtime));
+    assert_eq!(protocol_version, Some(1));
+    assert_eq!(Arc::strong_count(&runtime), 1);
+
+    // A broken new module doesn't replace a working one
+    std::fs::write(&module, "not a wasm module").unwrap();
+    service.reload_providers(vec![module.clone()]).await;
+    let (kept_runtime, protocol_version) =
+        loaded_runtime(&*service.inner.wasm_modules.read().await);
+    assert!(Arc::ptr_eq(&new_runtime, &kept_runtime));
+    assert_eq!(protocol_version, Some(1));
+}
+
+#[test]
+fn detects_the_protocol_version_of_providers() {
+    let providers = Path::new(env!("CARGO_MANIFEST_DIR")).join("providers");
+    let detect =
+        |name: &str| detect_protocol_version(&std::fs::read(providers.join(name)).unwrap());
+
+    assert_eq!(detect("elasticsearch.wasm"), Some(1));
+    assert_eq!(detect("loki.wasm"), Some(1));
+    assert_eq!(detect("prometheus.wasm"), Some(2));
+    assert_eq!(detect("sentry.wasm"), Some(2));
+    assert_eq!(detect_protocol_version(b"not a wasm module"), None);
+    // An empty module, which exports nothing
+    assert_eq!(detect_protocol_version(b"\0asm\x01\0\0\0"), None);
+}
+
+#[test]
+fn detects_the_providers_exporting_a_config_schema() {
+    let providers = Path::new(env!("CARGO_MANIFEST_DIR")).join("providers");
+    let exports = |name: &str| exports_config_schema(&std::fs::read(providers.join(name)).unwrap());
+
+    assert!(exports("https.wasm"));
+    assert!(!exports("prometheus.wasm"));
+    assert!(!exports("sentry.wasm"));
+    assert!(!exports_config_schema(b"not a wasm module"));
+}
+
+#[test]
+fn data_sources_can_override_the_protocol_version() {
+    let wasm_dir = Path::new("/providers");
+    let wasm_modules: WasmModules = vec![(
+        wasm_dir.join("prometheus.wasm"),
+        Err(Error::Invocation {
+            message: "not loaded".to_string(),
+        }),
+    )]
+    .into_iter()
+    .collect();
+
+    let data_source = unchecked_data_source("metrics", None);
+    assert_eq!(data_source.protocol_version(wasm_dir, &wasm_modules), 2);
+    let data_source = ProxyDataSource {
+        protocol_version: Some(1),
+        ..data_source
+    };
+    assert_eq!(data_source.protocol_version(wasm_dir, &wasm_modules), 1);
+}
+
+#[tokio::test]
+async fn spots_the_data_sources_with_an_undetected_protocol_version() {
+    let providers = Path::new(env!("CARGO_MANIFEST_DIR")).join("providers");
+    let wasm_dir = tempfile::TempDir::new().unwrap();
+    let wasm_dir = wasm_dir.path();
+    for provider in ["loki.wasm", "prometheus.wasm"] {
+        std::fs::copy(providers.join(provider), wasm_dir.join(provider)).unwrap();
+    }
+    // An empty module, which exports nothing
+    std::fs::write(wasm_dir.join("empty.wasm"), b"\0asm\x01\0\0\0").unwrap();
+
+    let data_source = |name: &'static str, provider_type: &str| ProxyDataSource {
+        provider_type: provider_type.to_string(),
+        ..unchecked_data_source(name, None)
+    };
+    let data_sources = vec![
+        data_source("logs", "loki"),
+        data_source("metrics", "prometheus"),
+        data_source("unknown", "empty"),
+        ProxyDataSource {
+            protocol_version: Some(2),
+            ..data_source("overridden", "empty")
+        },
+    ];
+    let (data_sources, wasm_modules) = super::load_data_sources(wasm_dir, data_sources).await;
+
+    let undetected = |name: &'static str| {
+        let data_source = &data_sources[&Name::from_static(name)];
+        (
+            data_source.has_undetected_protocol_version(wasm_dir, &wasm_modules),
+            data_source.protocol_version(wasm_dir, &wasm_modules),
+        )
+    };
+    assert_eq!(undetected("logs"), (false, 1));
+    assert_eq!(undetected("metrics"), (false, 2));
+    assert_eq!(undetected("unknown"), (true, 2));
+    assert_eq!(undetected("overridden"), (false, 2));
+}
+
+#[tokio::test]
+async fn status_checks_bypass_the_query_limits() {
+    let prometheus = httpmock::MockServer::start_async().await;
+    prometheus.mock(|when, then| {
+        when.method("GET").path("/api/v1/query");
+        then.status(200).body("{}");
+    });
+    let mut config = Map::new();
+    config.insert("url".to_string(), prometheus.url("").into());
+    let data_source = ProxyDataSource::new(
+        Name::from_static("prometheus-dev"),
+        "prometheus".to_string(),
+        config,
+    );
+    let service = ProxyService::init_local(
+        Path::new("./providers"),
+        vec![data_source.clone()],
+        QueryLimits {
+            max_concurrent_queries: Some(1),
+            max_queued_queries: 0,
+            ..Default::default()
+        },
+    )
+    .await;
+    let _running = service
+        .inner
+        .global_query_slots
+        .as_ref()
+        .unwrap()
+        .acquire()
+        .await
+        .expect("a slot is free");
+
+    let message = ServerMessage::new_invoke_proxy_request(
+        STATUS_REQUEST_V2.clone(),
+        data_source.name.clone(),
+        2,
+        Base64Uuid::new(),
+    );
+    let response = service.handle_message_inner(message).await.unwrap();
+    assert!(matches!(
+        response.payload,
+        ProxyMessagePayload::Error(ErrorMessage {
+            error: Error::Other { .. },
+            ..
+        })
+    ));
+
+    service
+        .check_status(&data_source, 2)
+        .await
+        .expect("status checks don't wait for a query slot");
+}
+
+#[test]
+fn requires_a_local_api_token_on_non_loopback_addresses() {
+    let config = |listen_address: &str, local_api: bool, token: Option<&str>| HttpServerConfig {
+        listen_address: listen_address.parse().unwrap(),
+        local_api,
+        local_api_token: token.map(ToString::to_string),
+    };
+
+    assert!(config("127.0.0.1:3000", true, None).check().is_ok());
+    assert!(config("[::1]:3000", true, None).check().is_ok());
+    assert!(config("0.0.0.0:3000", false, None).check().is_ok());
+    assert!(config("0.0.0.0:3000", true, Some("secret")).check().is_ok());
+
+    let err = config("0.0.0.0:3000", true, None).check().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "--local-api requires --local-api-token when listening on 0.0.0.0:3000, which isn't a loopback address"
+    );
+    assert!(config("192.168.1.10:3000", true, Some("")).check().is_err());
+}
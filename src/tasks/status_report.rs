@@ -0,0 +1,136 @@
+//! One-off report of the status of every data source, checked locally without
+//! connecting to Fiberplane
+
+use super::service::{ProxyDataSource, ProxyService, QueryLimits};
+use super::table::write_table;
+use crate::data_sources;
+use anyhow::Result;
+use futures::future::join_all;
+use serde::Serialize;
+use std::{fmt, path::Path, time::Instant};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthStatus {
+    Connected,
+    Error,
+    /// The data source disables its status checks, and its provider is loaded
+    Skipped,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HealthStatus::Connected => "connected",
+            HealthStatus::Error => "error",
+            HealthStatus::Skipped => "skipped",
+        })
+    }
+}
+
+/// Result of the status check of a data source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataSourceHealth {
+    pub name: String,
+    pub provider_type: String,
+    pub protocol_version: u8,
+    pub status: HealthStatus,
+    /// Duration of the status check, in milliseconds
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// The status of all the data sources, in the order of the configuration
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub data_sources: Vec<DataSourceHealth>,
+}
+
+impl HealthReport {
+    pub fn failed(&self) -> usize {
+        self.data_sources
+            .iter()
+            .filter(|data_source| data_source.status == HealthStatus::Error)
+            .count()
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.data_sources.is_empty() {
+            return writeln!(f, "No data sources configured");
+        }
+
+        let header = ["NAME", "PROVIDER", "PROTOCOL", "STATUS", "LATENCY", "ERROR"];
+        let rows: Vec<[String; 6]> = self
+            .data_sources
+            .iter()
+            .map(|data_source| {
+                [
+                    data_source.name.clone(),
+                    data_source.provider_type.clone(),
+                    format!("v{}", data_source.protocol_version),
+                    data_source.status.to_string(),
+                    data_source
+                        .latency_ms
+                        .map_or_else(|| "-".to_string(), |latency| format!("{latency}ms")),
+                    data_source.error.clone().unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect();
+        write_table(f, &header, &rows)
+    }
+}
+
+/// Load the data sources at `data_sources_path` and the providers they use from
+/// `wasm_dir`, and check the status of every data source once, in parallel.
+pub async fn check(
+    wasm_dir: &Path,
+    data_sources_path: &Path,
+    query_limits: QueryLimits,
+) -> Result<HealthReport> {
+    let files = data_sources::read(data_sources_path).await?;
+    let data_sources = data_sources::parse(&files)?;
+    let service = ProxyService::init_local(wasm_dir, data_sources.clone(), query_limits).await;
+
+    Ok(HealthReport {
+        data_sources: check_data_sources(&service, &data_sources).await,
+    })
+}
+
+/// Check the status of the given data sources of `service` once, in parallel
+pub(crate) async fn check_data_sources(
+    service: &ProxyService,
+    data_sources: &[ProxyDataSource],
+) -> Vec<DataSourceHealth> {
+    join_all(data_sources.iter().map(|data_source| async move {
+        let protocol_version = service.protocol_version(data_source).await;
+        let (status, latency_ms, error) = if data_source.status_check_enabled() {
+            let start = Instant::now();
+            let result = service.check_status(data_source, protocol_version).await;
+            let latency_ms = start.elapsed().as_millis() as u64;
+            match result {
+                Ok(()) => (HealthStatus::Connected, Some(latency_ms), None),
+                Err(err) => (HealthStatus::Error, Some(latency_ms), Some(err.to_string())),
+            }
+        } else {
+            match service.check_provider_module(data_source).await {
+                Ok(()) => (HealthStatus::Skipped, None, None),
+                Err(err) => (HealthStatus::Error, None, Some(err.to_string())),
+            }
+        };
+        DataSourceHealth {
+            name: data_source.name.to_string(),
+            provider_type: data_source.provider_type.clone(),
+            protocol_version,
+            status,
+            latency_ms,
+            error,
+        }
+    }))
+    .await
+}
@@ -0,0 +1,77 @@
+use super::{check, DataSourceHealth, HealthReport, HealthStatus};
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn formats_the_report_as_a_table() {
+    let report = HealthReport {
+        data_sources: vec![
+            DataSourceHealth {
+                name: "prometheus-prod".to_string(),
+                provider_type: "prometheus".to_string(),
+                protocol_version: 2,
+                status: HealthStatus::Connected,
+                latency_ms: Some(42),
+                error: None,
+            },
+            DataSourceHealth {
+                name: "logs".to_string(),
+                provider_type: "loki".to_string(),
+                protocol_version: 1,
+                status: HealthStatus::Error,
+                latency_ms: Some(1200),
+                error: Some("connection refused".to_string()),
+            },
+            DataSourceHealth {
+                name: "sentry".to_string(),
+                provider_type: "sentry".to_string(),
+                protocol_version: 2,
+                status: HealthStatus::Skipped,
+                latency_ms: None,
+                error: None,
+            },
+        ],
+    };
+
+    assert_eq!(report.failed(), 1);
+    assert_eq!(
+        report.to_string(),
+        "\
+NAME             PROVIDER    PROTOCOL  STATUS     LATENCY  ERROR
+prometheus-prod  prometheus  v2        connected  42ms     -
+logs             loki        v1        error      1200ms   connection refused
+sentry           sentry      v2        skipped    -        -
+"
+    );
+}
+
+#[tokio::test]
+async fn checks_every_data_source() {
+    let dir = TempDir::new().unwrap();
+    let dir = dir.path();
+    fs::write(dir.join("broken.wasm"), "not a wasm module").unwrap();
+    let data_sources_path = dir.join("data_sources.yaml");
+    fs::write(
+        &data_sources_path,
+        "- name: broken\n  providerType: broken\n  config: {}\n\
+         - name: unchecked\n  providerType: broken\n  protocolVersion: 1\n  config: {}\n  statusCheck:\n    enabled: false\n",
+    )
+    .unwrap();
+
+    let report = check(dir, &data_sources_path, Default::default())
+        .await
+        .unwrap();
+
+    assert_eq!(report.failed(), 2);
+    let broken = &report.data_sources[0];
+    assert_eq!(broken.name, "broken");
+    assert_eq!(broken.protocol_version, 2);
+    assert_eq!(broken.status, HealthStatus::Error);
+    assert!(broken.latency_ms.is_some());
+    assert!(broken.error.is_some());
+    let unchecked = &report.data_sources[1];
+    assert_eq!(unchecked.protocol_version, 1);
+    assert_eq!(unchecked.status, HealthStatus::Error);
+    assert_eq!(unchecked.latency_ms, None);
+    assert_eq!(unchecked.error, broken.error);
+}
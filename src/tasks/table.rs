@@ -0,0 +1,31 @@
+//! Plain text tables printed by the subcommands
+
+use std::fmt;
+
+/// Write `rows` under `header`, with the columns aligned on their widest cell
+pub(crate) fn write_table<R: AsRef<[String]>>(
+    f: &mut fmt::Formatter<'_>,
+    header: &[&str],
+    rows: &[R],
+) -> fmt::Result {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row.as_ref()[column].len())
+                .chain(std::iter::once(header[column].len()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let header: Vec<String> = header.iter().map(|cell| cell.to_string()).collect();
+    for row in std::iter::once(header.as_slice()).chain(rows.iter().map(AsRef::as_ref)) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(f, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use super::service::{ProxyDataSource, ProxyService, WasmModules};
+use super::service::{HttpServerConfig, ProxyDataSource, ProxyService, WasmModules};
 use fiberplane::base64uuid::Base64Uuid;
 use fiberplane::models::providers::{Error, HttpRequestError, TIMESERIES_QUERY_TYPE};
 use fiberplane::models::{data_sources::DataSourceStatus, names::Name, proxies::*};
@@ -27,16 +27,41 @@ static TOKEN: Lazy<ProxyToken> = Lazy::new(|| {
 async fn mock_prometheus() -> (MockServer, Vec<ProxyDataSource>) {
     let prometheus = MockServer::start_async().await;
 
-    let data_sources = vec![ProxyDataSource {
-        name: Name::from_static("prometheus-dev"),
-        description: None,
-        provider_type: "prometheus".to_string(),
-        config: Map::from_iter(vec![("url".to_string(), Value::String(prometheus.url("")))]),
-    }];
+    let data_sources = vec![ProxyDataSource::new(
+        Name::from_static("prometheus-dev"),
+        "prometheus".to_string(),
+        Map::from_iter(vec![("url".to_string(), Value::String(prometheus.url("")))]),
+    )];
 
     (prometheus, data_sources)
 }
 
+/// Return the sorted names of the data sources in a SetDataSources message,
+/// asserting that all of them are connected
+fn connected_data_source_names(message: Message) -> Vec<Name> {
+    let message = match message {
+        Message::Binary(message) => ProxyMessage::deserialize_msgpack(message).unwrap(),
+        _ => panic!("wrong type"),
+    };
+    let data_sources =
+        if let ProxyMessagePayload::SetDataSources(SetDataSourcesMessage { data_sources, .. }) =
+            message.payload
+        {
+            data_sources
+        } else {
+            panic!("wrong type");
+        };
+    let mut names: Vec<Name> = data_sources
+        .into_iter()
+        .map(|data_source| {
+            assert_eq!(data_source.status, DataSourceStatus::Connected);
+            data_source.name
+        })
+        .collect();
+    names.sort();
+    names
+}
+
 #[test]
 fn parses_data_sources_from_yaml() {
     let yaml = "
@@ -89,11 +114,13 @@ async fn sends_auth_token_header() {
     let service = ProxyService::new(
         format!("http://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         Default::default(),
         Default::default(),
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     );
 
     let handle_connection = async move {
@@ -143,32 +170,28 @@ async fn sends_data_sources_on_connect() {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
     let data_sources = vec![
-        ProxyDataSource {
-            name: Name::from_static("connected-prometheus"),
-            provider_type: "prometheus".to_string(),
-            description: None,
-            config: Map::from_iter([("url".into(), json!(connected_prometheus.url("")))]),
-        },
-        ProxyDataSource {
-            name: Name::from_static("connected-elasticsearch"),
-            provider_type: "elasticsearch".to_string(),
-            description: None,
-            config: Map::from_iter([("url".into(), json!(connected_elasticsearch.url("")))]),
-        },
-        ProxyDataSource {
-            name: Name::from_static("disconnected-prometheus"),
-            provider_type: "prometheus".to_string(),
-            description: None,
-            config: Map::from_iter([("url".into(), json!(disconnected_prometheus.url("")))]),
-        },
+        ProxyDataSource::new(
+            Name::from_static("connected-prometheus"),
+            "prometheus".to_string(),
+            Map::from_iter([("url".into(), json!(connected_prometheus.url("")))]),
+        ),
+        ProxyDataSource::new(
+            Name::from_static("connected-elasticsearch"),
+            "elasticsearch".to_string(),
+            Map::from_iter([("url".into(), json!(connected_elasticsearch.url("")))]),
+        ),
+        ProxyDataSource::new(
+            Name::from_static("disconnected-prometheus"),
+            "prometheus".to_string(),
+            Map::from_iter([("url".into(), json!(disconnected_prometheus.url("")))]),
+        ),
         // We don't have the proxy provider wasm module so this tests
         // what happens if you specify a provider that we don't have
-        ProxyDataSource {
-            name: Name::from_static("unknown-data-source"),
-            provider_type: "other-provider".to_string(),
-            description: None,
-            config: Map::from_iter([("url".into(), json!("http://localhost:1234"))]),
-        },
+        ProxyDataSource::new(
+            Name::from_static("unknown-data-source"),
+            "other-provider".to_string(),
+            Map::from_iter([("url".into(), json!("http://localhost:1234"))]),
+        ),
     ];
     let service = ProxyService::init(
         format!("ws://{addr}").parse().unwrap(),
@@ -178,6 +201,7 @@ async fn sends_data_sources_on_connect() {
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     )
     .await;
 
@@ -284,6 +308,7 @@ async fn checks_data_source_status_on_interval() {
         5,
         None,
         Duration::from_millis(200),
+        Default::default(),
     )
     .await;
 
@@ -350,6 +375,69 @@ async fn checks_data_source_status_on_interval() {
     disconnected_prometheus_mock.assert();
 }
 
+#[test(tokio::test)]
+async fn sends_data_sources_on_reload() {
+    let (mock_server, mut data_sources) = mock_prometheus().await;
+    let prometheus_mock = mock_server.mock(|when, then| {
+        when.method("GET").path("/api/v1/query");
+        then.status(200).body("{}");
+    });
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = ProxyService::init(
+        format!("ws://{addr}").parse().unwrap(),
+        TOKEN.clone(),
+        Path::new("./providers"),
+        data_sources.clone(),
+        5,
+        None,
+        Duration::from_secs(300),
+        Default::default(),
+    )
+    .await;
+
+    data_sources.push(ProxyDataSource::new(
+        Name::from_static("prometheus-staging"),
+        "prometheus".to_string(),
+        Map::from_iter([("url".into(), json!(mock_server.url("")))]),
+    ));
+    let reloading_service = service.clone();
+
+    let handle_connection = async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = accept_hdr_async(stream, |_req: &Request<()>, mut res: Response<()>| {
+            res.headers_mut()
+                .insert("fp-conn-id", HeaderValue::from_static("conn-id"));
+            Ok(res)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            connected_data_source_names(ws.next().await.unwrap().unwrap()),
+            vec![Name::from_static("prometheus-dev")]
+        );
+
+        reloading_service.reload_data_sources(data_sources).await;
+
+        assert_eq!(
+            connected_data_source_names(ws.next().await.unwrap().unwrap()),
+            vec![
+                Name::from_static("prometheus-dev"),
+                Name::from_static("prometheus-staging"),
+            ]
+        );
+    };
+
+    let (tx, _) = broadcast::channel(3);
+    select! {
+      result = service.connect(tx).fuse() => result.unwrap(),
+      _ = handle_connection.fuse() => {}
+    }
+    prometheus_mock.assert_hits(3);
+}
+
 #[test(tokio::test)]
 async fn sends_pings() {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -357,11 +445,13 @@ async fn sends_pings() {
     let service = ProxyService::new(
         format!("ws://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         WasmModules::new(),
         Default::default(),
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     );
 
     tokio::time::pause();
@@ -410,11 +500,17 @@ async fn health_check_endpoints() {
     let service = ProxyService::new(
         format!("ws://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         HashMap::new(),
         HashMap::new(),
         5,
-        Some(service_addr),
+        Some(HttpServerConfig {
+            listen_address: service_addr,
+            local_api: false,
+            local_api_token: None,
+        }),
         Duration::from_secs(300),
+        Default::default(),
     );
 
     let handle_connection = async move {
@@ -438,6 +534,7 @@ async fn health_check_endpoints() {
         // Check status while connected
         assert_eq!(StatusCode::OK, check_endpoint("").await);
         assert_eq!(StatusCode::OK, check_endpoint("/health").await);
+        assert_eq!(StatusCode::NOT_FOUND, check_endpoint("/api/").await);
 
         // Check status after disconnect
         drop(ws);
@@ -463,11 +560,13 @@ async fn returns_error_for_query_to_unknown_provider() {
     let service = ProxyService::new(
         format!("ws://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         WasmModules::new(),
         Default::default(),
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     );
 
     let handle_connection = async move {
@@ -570,6 +669,7 @@ async fn calls_provider_with_query_and_sends_result() {
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     )
     .await;
 
@@ -686,6 +786,7 @@ async fn handles_multiple_concurrent_messages() {
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     )
     .await;
 
@@ -809,6 +910,7 @@ async fn calls_provider_with_query_and_sends_error() {
         5,
         None,
         Duration::from_secs(300),
+        Default::default(),
     )
     .await;
 
@@ -874,11 +976,13 @@ async fn reconnects_if_websocket_closes() {
     let service = ProxyService::new(
         format!("ws://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         WasmModules::new(),
         Default::default(),
         1,
         None,
         Duration::from_secs(300),
+        Default::default(),
     );
 
     tokio::time::pause();
@@ -928,11 +1032,13 @@ async fn service_shutdown() {
     let service = ProxyService::new(
         format!("ws://{addr}").parse().unwrap(),
         TOKEN.clone(),
+        Path::new("./providers"),
         WasmModules::new(),
         Default::default(),
         1,
         None,
         Duration::from_secs(300),
+        Default::default(),
     );
 
     let (tx, _) = broadcast::channel(3);